@@ -0,0 +1,305 @@
+use crate::audit::{self, Action, Record};
+use crate::datastructures::config::Admin;
+use crate::httpd;
+use crate::query::QueryConn;
+use crate::storage::{key_client, key_parent, Storage};
+use crate::transfer;
+use anyhow::anyhow;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::info;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+/// Requests are carried out by `staff` of target server, which owns the connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdminRequest {
+    Mappings,
+    Channels,
+    Delete(i64),
+    Reassign(i64, String),
+    Resync,
+}
+
+#[derive(Debug)]
+pub struct AdminMessage {
+    pub request: AdminRequest,
+    pub reply: oneshot::Sender<anyhow::Result<Value>>,
+}
+
+pub type AdminSender = mpsc::Sender<AdminMessage>;
+pub type AdminReceiver = mpsc::Receiver<AdminMessage>;
+
+async fn mappings(storage: &mut dyn Storage, server_id: &str) -> anyhow::Result<Vec<Value>> {
+    let mut v = Vec::new();
+    for key in storage.keys(server_id).await? {
+        if let Some(cid) = storage.get(&key).await? {
+            v.push(json!({
+                "owner": key_client(&key),
+                "parent": key_parent(&key),
+                "cid": cid,
+            }));
+        }
+    }
+    v.sort_by_key(|mapping| mapping["cid"].as_i64());
+    Ok(v)
+}
+
+/// Carry out request with staff's connection, `Resync` is left to caller.
+pub async fn handle(
+    conn: &mut dyn QueryConn,
+    storage: &mut dyn Storage,
+    server_id: &str,
+    privilege_group: i64,
+    request: &AdminRequest,
+) -> anyhow::Result<Value> {
+    match request {
+        AdminRequest::Mappings => Ok(Value::from(mappings(storage, server_id).await?)),
+        AdminRequest::Channels => {
+            let channels = conn
+                .query_channels()
+                .await
+                .map_err(|e| anyhow!("Got error while query channels: {:?}", e))?;
+            let mut v = mappings(storage, server_id).await?;
+            for mapping in &mut v {
+                let channel = channels
+                    .iter()
+                    .find(|channel| Some(channel.cid()) == mapping["cid"].as_i64());
+                mapping["exists"] = Value::from(channel.is_some());
+                if let Some(channel) = channel {
+                    mapping["name"] = Value::from(channel.channel_name());
+                    mapping["total_clients"] = Value::from(channel.total_clients());
+                }
+            }
+            Ok(Value::from(v))
+        }
+        AdminRequest::Delete(cid) => {
+            let key = transfer::find_owner(storage, server_id, *cid)
+                .await?
+                .ok_or_else(|| anyhow!("Channel {} is not created by us", cid))?;
+            let ret = conn.delete_channel(*cid, true).await;
+            Record::new(Action::DeleteChannel, server_id, *cid, audit::code(&ret))
+                .owner(key_client(&key))
+                .detail("admin")
+                .write();
+            if let Err(e) = ret {
+                // 768 means channel is already gone
+                if e.code() != 768 {
+                    return Err(anyhow!("Got error while delete channel: {:?}", e));
+                }
+            }
+            storage.del(&key).await?;
+            info!("Channel {} is deleted by admin", cid);
+            Ok(Value::Null)
+        }
+        AdminRequest::Reassign(cid, target) => {
+            let key = transfer::find_owner(storage, server_id, *cid)
+                .await?
+                .ok_or_else(|| anyhow!("Channel {} is not created by us", cid))?;
+            transfer::transfer(
+                conn,
+                storage,
+                server_id,
+                privilege_group,
+                &key,
+                *cid,
+                target,
+            )
+            .await?;
+            Ok(Value::Null)
+        }
+        AdminRequest::Resync => Ok(Value::Null),
+    }
+}
+
+struct State {
+    token: String,
+    senders: HashMap<i64, AdminSender>,
+}
+
+fn response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    response(status, json!({ "error": message }))
+}
+
+// Compare in constant time, so token can't be guessed by response time
+fn token_eq(a: &str, b: &str) -> bool {
+    !b.is_empty()
+        && a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+// Every endpoint is under `/servers/{server_id}`, except server list itself
+async fn route(state: Arc<State>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let authorized = req
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token_eq(token, &state.token));
+    if !authorized {
+        return Ok(error_response(StatusCode::UNAUTHORIZED, "Unauthorized"));
+    }
+
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+
+    let (server_id, segments) = match segments.as_slice() {
+        ["servers"] if method == Method::GET => {
+            let mut server_ids = state.senders.keys().copied().collect::<Vec<_>>();
+            server_ids.sort_unstable();
+            return Ok(response(StatusCode::OK, Value::from(server_ids)));
+        }
+        ["servers", server_id, segments @ ..] => match server_id.parse::<i64>() {
+            Ok(server_id) => (server_id, segments),
+            Err(_) => return Ok(error_response(StatusCode::NOT_FOUND, "Not found")),
+        },
+        _ => return Ok(error_response(StatusCode::NOT_FOUND, "Not found")),
+    };
+
+    let request = match (&method, segments) {
+        (&Method::GET, ["mappings"]) => AdminRequest::Mappings,
+        (&Method::GET, ["channels"]) => AdminRequest::Channels,
+        (&Method::DELETE, ["channels", cid]) => match cid.parse() {
+            Ok(cid) => AdminRequest::Delete(cid),
+            Err(_) => {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid channel id",
+                ))
+            }
+        },
+        (&Method::PUT, ["channels", cid, "owner"]) => {
+            let cid = match cid.parse() {
+                Ok(cid) => cid,
+                Err(_) => {
+                    return Ok(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "Invalid channel id",
+                    ))
+                }
+            };
+            let body = hyper::body::to_bytes(req.into_body())
+                .await
+                .map(|body| String::from_utf8_lossy(&body).trim().to_string())
+                .unwrap_or_default();
+            if body.is_empty() {
+                return Ok(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Nickname or unique id of new owner is required",
+                ));
+            }
+            AdminRequest::Reassign(cid, body)
+        }
+        (&Method::POST, ["resync"]) => AdminRequest::Resync,
+        _ => return Ok(error_response(StatusCode::NOT_FOUND, "Not found")),
+    };
+
+    let sender = match state.senders.get(&server_id) {
+        Some(sender) => sender,
+        None => return Ok(error_response(StatusCode::NOT_FOUND, "Server not found")),
+    };
+    let (reply, receiver) = oneshot::channel();
+    if sender.send(AdminMessage { request, reply }).await.is_err() {
+        return Ok(error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server is not running",
+        ));
+    }
+    Ok(match receiver.await {
+        Ok(Ok(data)) => response(StatusCode::OK, json!({ "data": data })),
+        Ok(Err(e)) => error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        Err(_) => error_response(StatusCode::SERVICE_UNAVAILABLE, "Server is not running"),
+    })
+}
+
+pub fn bind(
+    admin: &Admin,
+    senders: HashMap<i64, AdminSender>,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<()>>> {
+    let state = Arc::new(State {
+        token: admin.token().to_string(),
+        senders,
+    });
+    httpd::bind("Admin API", admin.listen(), move |req| {
+        route(state.clone(), req)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::admin::{route, AdminRequest, State};
+    use hyper::{Body, Request, StatusCode};
+    use serde_json::Value;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        let state = Arc::new(State {
+            token: "secret".to_string(),
+            senders: HashMap::from([(1, sender)]),
+        });
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                let data = match message.request {
+                    AdminRequest::Reassign(cid, target) => {
+                        Value::from(format!("{} {}", cid, target))
+                    }
+                    _ => Value::Null,
+                };
+                message.reply.send(Ok(data)).unwrap();
+            }
+        });
+        let request = |method: &str, path: &str, token: &str, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        for token in ["wrong", "", "secret "] {
+            let ret = route(state.clone(), request("GET", "/servers", token, ""))
+                .await
+                .unwrap();
+            assert_eq!(ret.status(), StatusCode::UNAUTHORIZED);
+        }
+        let ret = route(
+            state.clone(),
+            request("GET", "/servers/2/mappings", "secret", ""),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ret.status(), StatusCode::NOT_FOUND);
+
+        let ret = route(
+            state.clone(),
+            request("PUT", "/servers/1/channels/20/owner", "secret", "foo\n"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(ret.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap()["data"],
+            "20 foo"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::datastructures::{Client, QueryResult};
+use anyhow::anyhow;
+use log::error;
+use once_cell::sync::OnceCell;
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static AUDIT_LOG: OnceCell<Mutex<File>> = OnceCell::new();
+
+/// `code` of action which succeed.
+pub const CODE_OK: i32 = 0;
+/// `code` of action which failed without error id from server, e.g. target not found or storage error.
+pub const CODE_FAILED: i32 = -2;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    CreateChannel,
+    GrantPermission,
+    MoveClient,
+    DeleteChannel,
+    Transfer,
+    Command,
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "create_channel" => Self::CreateChannel,
+            "grant_permission" => Self::GrantPermission,
+            "move_client" => Self::MoveClient,
+            "delete_channel" => Self::DeleteChannel,
+            "transfer" => Self::Transfer,
+            "command" => Self::Command,
+            _ => return Err(anyhow!("Unknown action: {}", s)),
+        })
+    }
+}
+
+/// One line of audit log, `code` is the error id returned by server (0 means ok).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Record {
+    pub timestamp: u64,
+    pub server: String,
+    pub action: Option<Action>,
+    pub client_uid: String,
+    pub client_database_id: Option<i64>,
+    pub nickname: String,
+    pub channel_id: i64,
+    pub code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Record {
+    pub fn new(action: Action, server: &str, channel_id: i64, code: i32) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+            server: server.to_string(),
+            action: Some(action),
+            channel_id,
+            code,
+            ..Default::default()
+        }
+    }
+
+    pub fn client(mut self, client: &Client) -> Self {
+        self.client_uid = client.client_unique_identifier().to_string();
+        self.client_database_id = Some(client.client_database_id());
+        self.nickname = client.client_nickname().to_string();
+        self
+    }
+
+    // Only database id is known if client is not online
+    pub fn owner(mut self, client_database_id: Option<i64>) -> Self {
+        self.client_database_id = client_database_id;
+        self
+    }
+
+    pub fn detail<S: Into<String>>(mut self, detail: S) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Append to audit log, do nothing if audit log is disabled.
+    pub fn write(self) {
+        let file = match AUDIT_LOG.get() {
+            Some(file) => file,
+            None => return,
+        };
+        let line = match serde_json::to_string(&self) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Serialize audit record error: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = writeln!(file.lock().unwrap(), "{}", line) {
+            error!("Write audit log error: {:?}", e);
+        }
+    }
+
+    fn matches(&self, filter: &Filter) -> bool {
+        filter.client.as_ref().is_none_or(|client| {
+            self.client_uid == *client
+                || self.nickname.eq_ignore_ascii_case(client)
+                || self.client_database_id.map(|id| id.to_string()).as_ref() == Some(client)
+        }) && filter.channel_id.is_none_or(|cid| self.channel_id == cid)
+            && filter
+                .action
+                .is_none_or(|action| self.action == Some(action))
+            && filter.since.is_none_or(|since| self.timestamp >= since)
+    }
+}
+
+/// Error id of `result`, 0 if succeed.
+pub fn code<T>(result: &QueryResult<T>) -> i32 {
+    result.as_ref().err().map_or(CODE_OK, |e| e.code())
+}
+
+pub fn open(path: &Path) -> anyhow::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("Open audit log {} error: {:?}", path.display(), e))?;
+    AUDIT_LOG
+        .set(Mutex::new(file))
+        .map_err(|_| anyhow!("Audit log is already opened"))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    // Unique id, database id or nickname
+    pub client: Option<String>,
+    pub channel_id: Option<i64>,
+    pub action: Option<Action>,
+    pub since: Option<u64>,
+}
+
+/// Records matching `filter`, latest `limit` records only.
+pub fn query(path: &Path, filter: &Filter, limit: usize) -> anyhow::Result<Vec<Record>> {
+    let file = File::open(path)
+        .map_err(|e| anyhow!("Open audit log {} error: {:?}", path.display(), e))?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(&line).map_err(|e| anyhow!("Parse audit log error: {:?}", e))?;
+        if record.matches(filter) {
+            records.push(record);
+        }
+    }
+    let skip = records.len().saturating_sub(limit);
+    Ok(records.split_off(skip))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::audit::{query, Action, Filter, Record};
+    use std::io::Write;
+
+    #[test]
+    fn test() {
+        let path = std::env::temp_dir().join(format!("ts-audit-{}.jsonl", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        for (action, cid) in [
+            (Action::CreateChannel, 20),
+            (Action::MoveClient, 20),
+            (Action::DeleteChannel, 21),
+        ] {
+            let record = Record::new(action, "uid", cid, 0).owner(Some(10));
+            writeln!(file, "{}", serde_json::to_string(&record).unwrap()).unwrap();
+        }
+
+        let filter = Filter {
+            channel_id: Some(20),
+            ..Default::default()
+        };
+        let records = query(&path, &filter, 10).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            query(&path, &filter, 1).unwrap()[0].action,
+            Some(Action::MoveClient)
+        );
+
+        let filter = Filter {
+            client: Some("10".to_string()),
+            action: Some(Action::DeleteChannel),
+            ..Default::default()
+        };
+        assert_eq!(query(&path, &filter, 10).unwrap()[0].channel_id, 21);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
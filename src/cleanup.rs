@@ -0,0 +1,125 @@
+use crate::audit::{self, Action, Record};
+use crate::datastructures::config::{ChannelType, Cleanup, MonitorChannel};
+use crate::metrics::METRICS;
+use crate::query::QueryConn;
+use crate::storage::{key_client, key_parent, Storage};
+use anyhow::anyhow;
+use log::{debug, error, info};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub struct Cleaner {
+    grace_period: Duration,
+    interval: Duration,
+    next_run: Instant,
+    empty_since: HashMap<i64, Instant>,
+}
+
+impl Cleaner {
+    pub fn new(config: &Cleanup) -> Self {
+        Self {
+            grace_period: Duration::from_secs(config.grace_period()),
+            interval: Duration::from_secs(config.interval()),
+            next_run: Instant::now(),
+            empty_since: Default::default(),
+        }
+    }
+
+    /// Apply new settings, channels already seen empty keep their time.
+    pub fn update(&mut self, config: &Cleanup) {
+        self.grace_period = Duration::from_secs(config.grace_period());
+        self.interval = Duration::from_secs(config.interval());
+        self.next_run = self.next_run.min(Instant::now() + self.interval);
+    }
+
+    pub fn next_run(&self) -> Instant {
+        self.next_run
+    }
+
+    pub fn due(&self) -> bool {
+        Instant::now() >= self.next_run
+    }
+
+    pub async fn sweep(
+        &mut self,
+        conn: &mut dyn QueryConn,
+        storage: &mut dyn Storage,
+        server_id: &str,
+        channel_options: &HashMap<i64, MonitorChannel>,
+    ) -> anyhow::Result<()> {
+        self.next_run = Instant::now() + self.interval;
+
+        let keys = storage.keys(server_id).await?;
+        if keys.is_empty() {
+            METRICS.set_auto_channels(server_id, 0);
+            self.empty_since.clear();
+            return Ok(());
+        }
+
+        let channels = conn
+            .query_channels()
+            .await
+            .map_err(|e| anyhow!("Got error while query channels: {:?}", e))?
+            .into_iter()
+            .map(|channel| (channel.cid(), channel))
+            .collect::<HashMap<_, _>>();
+
+        let mut tracked = HashMap::new();
+        let mut alive = 0;
+        for key in keys {
+            let cid = match storage.get(&key).await? {
+                Some(cid) => cid,
+                None => continue,
+            };
+
+            let channel = match channels.get(&cid) {
+                Some(channel) => channel,
+                None => {
+                    debug!("Channel {} is gone, remove key {}", cid, key);
+                    storage.del(&key).await?;
+                    continue;
+                }
+            };
+
+            alive += 1;
+            if channel.total_clients() > 0 {
+                continue;
+            }
+
+            // Permanent channels are kept, temporary channels are deleted by server itself
+            let channel_type = key_parent(&key)
+                .and_then(|pid| channel_options.get(&pid))
+                .and_then(|option| option.channel_type());
+            if matches!(
+                channel_type,
+                Some(ChannelType::Permanent | ChannelType::Temporary)
+            ) {
+                continue;
+            }
+
+            let since = *self.empty_since.get(&cid).unwrap_or(&Instant::now());
+            if since.elapsed() < self.grace_period {
+                tracked.insert(cid, since);
+                continue;
+            }
+
+            let ret = conn.delete_channel(cid, false).await;
+            Record::new(Action::DeleteChannel, server_id, cid, audit::code(&ret))
+                .owner(key_client(&key))
+                .detail("empty")
+                .write();
+            match ret {
+                Ok(_) => {
+                    alive -= 1;
+                    storage.del(&key).await?;
+                    info!("Delete empty channel {} ({})", channel.channel_name(), cid);
+                }
+                Err(e) => error!("Got error while delete channel {}: {:?}", cid, e),
+            }
+        }
+        // Forget channels which is deleted, reused or no longer mapped
+        self.empty_since = tracked;
+        METRICS.set_auto_channels(server_id, alive);
+        Ok(())
+    }
+}
@@ -0,0 +1,243 @@
+use crate::audit;
+use crate::datastructures::config::{Server, StorageType};
+use crate::datastructures::Config;
+use crate::dryrun::{DryRunConn, DryRunStorage};
+use crate::query::QueryConn;
+use crate::storage::{self, key_client, key_parent};
+use crate::transfer;
+use crate::validate::validate;
+use crate::{open_storage, try_init_connection, SYSTEMD_MODE};
+use anyhow::anyhow;
+use std::collections::HashSet;
+use std::path::Path;
+
+// Operations below run once, do not wait if server is not reachable
+async fn connect(config: &Config, server: &Server) -> anyhow::Result<(Box<dyn QueryConn>, String)> {
+    SYSTEMD_MODE.get_or_init(|| false);
+    let mut conn = try_init_connection(config, server.server_id()).await?;
+    let server_info = conn
+        .query_server_info()
+        .await
+        .map_err(|e| anyhow!("Query server info error: {:?}", e))?;
+    Ok((
+        conn,
+        server_info.virtualserver_unique_identifier().to_string(),
+    ))
+}
+
+// All servers if `server_id` is None
+fn select_servers(config: &Config, server_id: Option<i64>) -> anyhow::Result<Vec<&Server>> {
+    match server_id {
+        Some(server_id) => config
+            .server_by_id(server_id)
+            .map(|server| vec![server])
+            .ok_or_else(|| anyhow!("Server {} not found in configure file", server_id)),
+        None => Ok(config.servers().iter().collect()),
+    }
+}
+
+pub async fn check_config<P: AsRef<Path>>(path: P, server_id: Option<i64>) -> anyhow::Result<()> {
+    let config = Config::try_from(path.as_ref())?;
+    let mut count = 0;
+    for server in select_servers(&config, server_id)? {
+        let (mut conn, _) = connect(&config, server).await?;
+        let problems = validate(conn.as_mut(), &config, server).await?;
+        conn.logout().await.ok();
+        if problems.is_empty() {
+            println!("Server {}: ok", server.server_id());
+        }
+        for problem in &problems {
+            println!("Server {}: {}", server.server_id(), problem);
+        }
+        count += problems.len();
+    }
+    if count > 0 {
+        return Err(anyhow!("Found {} problem(s) in configure file", count));
+    }
+    Ok(())
+}
+
+pub async fn list<P: AsRef<Path>>(path: P, server_id: Option<i64>) -> anyhow::Result<()> {
+    let config = Config::try_from(path.as_ref())?;
+    let mut storage = open_storage(&config).await?;
+    println!("SERVER\tOWNER\tPARENT\tCHANNEL");
+    for server in select_servers(&config, server_id)? {
+        let (mut conn, server_uid) = connect(&config, server).await?;
+        conn.logout().await.ok();
+        let mut mappings = Vec::new();
+        for key in storage.keys(&server_uid).await? {
+            if let Some(cid) = storage.get(&key).await? {
+                mappings.push((key_client(&key), key_parent(&key), cid));
+            }
+        }
+        mappings.sort_unstable_by_key(|(_, _, cid)| *cid);
+        for (owner, parent, cid) in mappings {
+            println!(
+                "{}\t{}\t{}\t{}",
+                server.server_id(),
+                owner.unwrap_or_default(),
+                parent.unwrap_or_default(),
+                cid
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Remove mappings whose channel no longer exists.
+pub async fn purge<P: AsRef<Path>>(
+    path: P,
+    server_id: Option<i64>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let config = Config::try_from(path.as_ref())?;
+    let mut storage = open_storage(&config).await?;
+    for server in select_servers(&config, server_id)? {
+        let (mut conn, server_uid) = connect(&config, server).await?;
+        let channels = conn
+            .query_channels()
+            .await
+            .map_err(|e| anyhow!("Query channels error: {:?}", e))?
+            .into_iter()
+            .map(|channel| channel.cid())
+            .collect::<HashSet<_>>();
+        conn.logout().await.ok();
+
+        let mut count = 0;
+        for key in storage.keys(&server_uid).await? {
+            let cid = match storage.get(&key).await? {
+                Some(cid) if !channels.contains(&cid) => cid,
+                _ => continue,
+            };
+            if dry_run {
+                println!(
+                    "Server {}: would remove {} ({})",
+                    server.server_id(),
+                    key,
+                    cid
+                );
+            } else {
+                storage.del(&key).await?;
+                println!("Server {}: removed {} ({})", server.server_id(), key, cid);
+            }
+            count += 1;
+        }
+        println!("Server {}: {} mapping(s) purged", server.server_id(), count);
+    }
+    Ok(())
+}
+
+/// Copy mappings from storage in configure file to another storage backend.
+pub async fn migrate<P: AsRef<Path>>(
+    path: P,
+    server_id: Option<i64>,
+    to: StorageType,
+    redis_server: Option<&str>,
+    storage_path: Option<&Path>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let config = Config::try_from(path.as_ref())?;
+    let redis_server = redis_server
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| config.server().redis_server());
+    let storage_path = storage_path
+        .map(|path| path.to_path_buf())
+        .unwrap_or_else(|| to.default_path());
+    let same = match to {
+        StorageType::Redis => redis_server == config.server().redis_server(),
+        _ => storage_path == config.server().storage_path(),
+    };
+    if to == config.server().storage() && same {
+        return Err(anyhow!("Source and destination storage are the same"));
+    }
+
+    let mut source = open_storage(&config).await?;
+    let mut destination = storage::open(to, &redis_server, &storage_path).await?;
+    for server in select_servers(&config, server_id)? {
+        let (mut conn, server_uid) = connect(&config, server).await?;
+        conn.logout().await.ok();
+
+        let mut count = 0;
+        for key in source.keys(&server_uid).await? {
+            let cid = match source.get(&key).await? {
+                Some(cid) => cid,
+                None => continue,
+            };
+            if dry_run {
+                println!(
+                    "Server {}: would copy {} ({})",
+                    server.server_id(),
+                    key,
+                    cid
+                );
+            } else {
+                destination.set(&key, cid).await?;
+            }
+            count += 1;
+        }
+        println!(
+            "Server {}: {} mapping(s) migrated",
+            server.server_id(),
+            count
+        );
+    }
+    Ok(())
+}
+
+pub async fn transfer<P: AsRef<Path>>(
+    path: P,
+    server_id: Option<i64>,
+    cid: i64,
+    target: &str,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let config = Config::try_from(path.as_ref())?;
+    let server = match server_id {
+        Some(_) => select_servers(&config, server_id)?[0],
+        None => config.server(),
+    };
+
+    // Audit log is not written in dry run mode
+    if let Some(audit) = config.audit().as_ref().filter(|_| !dry_run) {
+        audit::open(&audit.path())?;
+    }
+    let (mut conn, server_uid) = connect(&config, server).await?;
+    let mut storage = open_storage(&config).await?;
+    if dry_run {
+        conn = Box::new(DryRunConn::new(conn));
+        storage = Box::new(DryRunStorage::new(storage));
+    }
+
+    let key = transfer::find_owner(storage.as_mut(), &server_uid, cid)
+        .await?
+        .ok_or_else(|| anyhow!("Channel {} is not created by us", cid))?;
+    transfer::transfer(
+        conn.as_mut(),
+        storage.as_mut(),
+        &server_uid,
+        server.privilege_group_id(),
+        &key,
+        cid,
+        target,
+    )
+    .await?;
+    conn.logout().await.ok();
+    if dry_run {
+        println!("Channel {} would be transferred to {}", cid, target);
+    } else {
+        println!("Channel {} is transferred to {}", cid, target);
+    }
+    Ok(())
+}
+
+pub fn audit<P: AsRef<Path>>(path: P, filter: &audit::Filter, limit: usize) -> anyhow::Result<()> {
+    let config = Config::try_from(path.as_ref())?;
+    let audit = config
+        .audit()
+        .as_ref()
+        .ok_or_else(|| anyhow!("Audit log is not enabled in configure file"))?;
+    for record in audit::query(&audit.path(), filter, limit)? {
+        println!("{}", serde_json::to_string(&record)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,117 @@
+use crate::datastructures::Notifies;
+use std::collections::VecDeque;
+
+const BANNER_HEADER: &str = "TS3";
+const BANNER_WELCOME: &str = "Welcome to the TeamSpeak 3";
+
+/// Line framed decoder of ServerQuery stream.
+///
+/// Lines are terminated by `\n\r`, a response is complete once `error id=` line
+/// is received, notify lines may arrive at any time and are queued separately.
+#[derive(Debug, Default)]
+pub struct LineCodec {
+    buffer: Vec<u8>,
+    pending: Vec<String>,
+    responses: VecDeque<String>,
+    notifies: VecDeque<String>,
+    banner: bool,
+}
+
+impl LineCodec {
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=pos).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_matches(|c| c == '\r' || c == '\n');
+            if line.is_empty() {
+                continue;
+            }
+            self.push_line(line);
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        if Notifies::is_notify(line) {
+            self.notifies.push_back(line.to_string());
+        } else if line.starts_with(BANNER_WELCOME) {
+            self.banner = true;
+        } else if line != BANNER_HEADER {
+            self.pending.push(line.to_string());
+            if line.starts_with("error ") {
+                self.responses.push_back(self.pending.join("\n"));
+                self.pending.clear();
+            }
+        }
+    }
+
+    pub fn next_response(&mut self) -> Option<String> {
+        self.responses.pop_front()
+    }
+
+    pub fn has_notifies(&self) -> bool {
+        !self.notifies.is_empty()
+    }
+
+    pub fn take_notifies(&mut self) -> Vec<String> {
+        self.notifies.drain(..).collect()
+    }
+
+    /// Return true once after welcome banner is received.
+    pub fn take_banner(&mut self) -> bool {
+        std::mem::take(&mut self.banner)
+    }
+
+    /// Drop everything belongs to previous connection, except notifies.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.pending.clear();
+        self.responses.clear();
+        self.banner = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::codec::LineCodec;
+
+    #[test]
+    fn test() {
+        let mut codec = LineCodec::default();
+        codec.feed(b"TS3\n\rWelcome to the TeamSpeak 3 ServerQuery interface.\n\r");
+        assert!(codec.take_banner());
+        assert!(codec.next_response().is_none());
+
+        // Reply split across reads, with notify interleaved
+        codec.feed(b"clid=1 cid=1|cl");
+        assert!(codec.next_response().is_none());
+        codec.feed(b"id=2 cid=3\n\rnotifyclientmoved ctid=5 reasonid=0 clid=7\n\rerror id=0 m");
+        assert!(codec.next_response().is_none());
+        assert!(codec.has_notifies());
+        codec.feed(
+            "sg=ok\n\rerror id=771 msg=channel\\sname\\sis\\salready\\sin\\suse\n\r".as_bytes(),
+        );
+        assert_eq!(
+            codec.next_response().unwrap(),
+            "clid=1 cid=1|clid=2 cid=3\nerror id=0 msg=ok"
+        );
+        assert!(codec.next_response().unwrap().starts_with("error id=771"));
+        assert!(codec.next_response().is_none());
+        assert_eq!(
+            codec.take_notifies(),
+            vec!["notifyclientmoved ctid=5 reasonid=0 clid=7"]
+        );
+
+        // Multi-line reply is kept intact
+        codec.feed(b"cid=1\n\rcid=2\n\rerror id=0 msg=ok\n\r");
+        assert_eq!(
+            codec.next_response().unwrap(),
+            "cid=1\ncid=2\nerror id=0 msg=ok"
+        );
+
+        codec.feed(b"cid=1");
+        codec.reset();
+        codec.feed(b"error id=0 msg=ok\n\r");
+        assert_eq!(codec.next_response().unwrap(), "error id=0 msg=ok");
+    }
+}
@@ -0,0 +1,316 @@
+use crate::audit::{self, Action, Record};
+use crate::datastructures::NotifyTextMessage;
+use crate::query::QueryConn;
+use crate::storage::{channel_key, Storage};
+use crate::transfer;
+use anyhow::anyhow;
+use log::info;
+
+const HELP_MESSAGE: &str = "Available commands: !name <name>, !limit [number], !password [password], !topic [topic], !lock, !unlock, !kick <nickname>, !transfer <nickname|uid>";
+
+/// Commands which channel owner can send to us by private message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Name(String),
+    // None means unlimited
+    Limit(Option<u32>),
+    Password(String),
+    Topic(String),
+    Lock,
+    Unlock,
+    Kick(String),
+    Transfer(String),
+}
+
+impl Command {
+    /// Return None if message is not a command.
+    pub fn parse(message: &str) -> Option<anyhow::Result<Self>> {
+        let message = message.trim().strip_prefix('!')?;
+        let (command, argument) = message.split_once(' ').unwrap_or((message, ""));
+        let argument = argument.trim().to_string();
+        Some(match command {
+            "name" if !argument.is_empty() => Ok(Self::Name(argument)),
+            "limit" if argument.is_empty() || argument == "0" => Ok(Self::Limit(None)),
+            "limit" => argument
+                .parse()
+                .map(|limit| Self::Limit(Some(limit)))
+                .map_err(|_| anyhow!("Limit should be a number")),
+            "password" => Ok(Self::Password(argument)),
+            "topic" => Ok(Self::Topic(argument)),
+            "lock" => Ok(Self::Lock),
+            "unlock" => Ok(Self::Unlock),
+            "kick" if !argument.is_empty() => Ok(Self::Kick(argument)),
+            "transfer" if !argument.is_empty() => Ok(Self::Transfer(argument)),
+            _ => Err(anyhow!("{}", HELP_MESSAGE)),
+        })
+    }
+}
+
+fn max_clients(limit: Option<u32>) -> Vec<(&'static str, String)> {
+    match limit {
+        Some(limit) => vec![
+            ("channel_flag_maxclients_unlimited", "0".to_string()),
+            ("channel_maxclients", limit.to_string()),
+        ],
+        None => vec![("channel_flag_maxclients_unlimited", "1".to_string())],
+    }
+}
+
+/// Carry out command in the channel which invoker is in, only if invoker owns it.
+pub async fn handle(
+    conn: &mut dyn QueryConn,
+    storage: &mut dyn Storage,
+    monitor_channels: &[i64],
+    server_id: &str,
+    privilege_group: i64,
+    message: &NotifyTextMessage,
+) -> anyhow::Result<()> {
+    let command = match Command::parse(message.msg()) {
+        None => return Ok(()),
+        Some(Ok(command)) => command,
+        Some(Err(e)) => return reply(conn, message, &e.to_string()).await,
+    };
+
+    let clients = conn
+        .query_clients()
+        .await
+        .map_err(|e| anyhow!("Got error while query clients: {:?}", e))?;
+    let invoker = clients
+        .iter()
+        .find(|client| client.client_id() == message.invoker_id())
+        .ok_or_else(|| anyhow!("Invoker {} not found", message.invoker_id()))?;
+
+    let mut owned = None;
+    for pid in monitor_channels {
+        let key = channel_key(invoker.client_database_id(), server_id, *pid);
+        if storage.get(&key).await? == Some(invoker.channel_id()) {
+            owned = Some(key);
+            break;
+        }
+    }
+    let key = match owned {
+        Some(key) => key,
+        None => {
+            return reply(
+                conn,
+                message,
+                "You can only use commands in your own channel.",
+            )
+            .await
+        }
+    };
+    let cid = invoker.channel_id();
+
+    let result = match &command {
+        Command::Name(name) => {
+            conn.edit_channel(cid, &[("channel_name", name.clone())])
+                .await
+        }
+        Command::Limit(limit) => conn.edit_channel(cid, &max_clients(*limit)).await,
+        Command::Password(password) => {
+            conn.edit_channel(cid, &[("channel_password", password.clone())])
+                .await
+        }
+        Command::Topic(topic) => {
+            conn.edit_channel(cid, &[("channel_topic", topic.clone())])
+                .await
+        }
+        // Nobody else can join until unlock
+        Command::Lock => {
+            let count = clients
+                .iter()
+                .filter(|client| client.channel_id() == cid)
+                .count();
+            conn.edit_channel(cid, &max_clients(Some(count as u32)))
+                .await
+        }
+        Command::Unlock => conn.edit_channel(cid, &max_clients(None)).await,
+        Command::Kick(nickname) => {
+            match clients.iter().find(|client| {
+                client.channel_id() == cid
+                    && client.client_id() != invoker.client_id()
+                    && client.client_nickname().eq_ignore_ascii_case(nickname)
+            }) {
+                Some(client) => {
+                    conn.kick_client_from_channel(client.client_id(), "Kicked by channel owner")
+                        .await
+                }
+                None => {
+                    return reply(
+                        conn,
+                        message,
+                        &format!("Can't find {} in your channel.", nickname),
+                    )
+                    .await
+                }
+            }
+        }
+        Command::Transfer(target) => {
+            // Not a single query, -2 is the code of local errors
+            let (text, code) = match transfer::transfer(
+                conn,
+                storage,
+                server_id,
+                privilege_group,
+                &key,
+                cid,
+                target,
+            )
+            .await
+            {
+                Ok(_) => ("Done.".to_string(), audit::CODE_OK),
+                Err(e) => (format!("Failed: {}", e), audit::CODE_FAILED),
+            };
+            Record::new(Action::Command, server_id, cid, code)
+                .client(invoker)
+                .detail(message.msg())
+                .write();
+            return reply(conn, message, &text).await;
+        }
+    };
+
+    Record::new(Action::Command, server_id, cid, audit::code(&result))
+        .client(invoker)
+        .detail(message.msg())
+        .write();
+    match result {
+        Ok(_) => {
+            info!(
+                "{} ({}) run {:?} in channel {}",
+                message.invoker_name(),
+                invoker.client_database_id(),
+                command,
+                cid
+            );
+            reply(conn, message, "Done.").await
+        }
+        Err(e) => reply(conn, message, &format!("Failed: {}", e)).await,
+    }
+}
+
+async fn reply(
+    conn: &mut dyn QueryConn,
+    message: &NotifyTextMessage,
+    text: &str,
+) -> anyhow::Result<()> {
+    conn.send_text_message(message.invoker_id(), text)
+        .await
+        .map_err(|e| anyhow!("Got error while send message: {:?}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::commands::{handle, Command};
+    use crate::datastructures::Notifies;
+    use crate::mock::MockServer;
+    use crate::socketlib::SocketConn;
+    use crate::storage::{channel_key, MemoryStorage, Storage};
+
+    #[tokio::test]
+    async fn test() {
+        assert_eq!(
+            Command::parse("!name  my room ").unwrap().unwrap(),
+            Command::Name("my room".to_string())
+        );
+        assert_eq!(
+            Command::parse("!limit 0").unwrap().unwrap(),
+            Command::Limit(None)
+        );
+        assert!(Command::parse("!limit many").unwrap().is_err());
+        assert!(Command::parse("!kick").unwrap().is_err());
+        assert!(Command::parse("hello").is_none());
+
+        let mock = MockServer::start().await;
+        let mut conn = SocketConn::connect(&mock.endpoint()).await.unwrap();
+        let mut storage = MemoryStorage::default();
+        mock.set_reply(
+            "clientlist",
+            "clid=5 cid=20 client_database_id=10 client_nickname=foo client_type=0|clid=6 cid=20 client_database_id=11 client_nickname=Bar client_type=0",
+        );
+        let message = |msg: &str| {
+            Notifies::from_line(&format!(
+                "notifytextmessage targetmode=1 msg={} invokerid=5 invokername=foo",
+                msg.replace(' ', "\\s")
+            ))
+            .unwrap()
+            .private_message()
+            .cloned()
+            .unwrap()
+        };
+
+        // Not owner
+        handle(&mut conn, &mut storage, &[1], "uid", 5, &message("!lock"))
+            .await
+            .unwrap();
+        assert!(!mock
+            .take_commands()
+            .iter()
+            .any(|command| command.starts_with("channeledit")));
+
+        storage.set(&channel_key(10, "uid", 1), 20).await.unwrap();
+        handle(&mut conn, &mut storage, &[1], "uid", 5, &message("!lock"))
+            .await
+            .unwrap();
+        assert!(mock.take_commands().contains(
+            &"channeledit cid=20 channel_flag_maxclients_unlimited=0 channel_maxclients=2"
+                .to_string()
+        ));
+        handle(
+            &mut conn,
+            &mut storage,
+            &[1],
+            "uid",
+            5,
+            &message("!kick bar"),
+        )
+        .await
+        .unwrap();
+        assert!(mock.take_commands().contains(
+            &"clientkick clid=6 reasonid=4 reasonmsg=Kicked\\sby\\schannel\\sowner".to_string()
+        ));
+
+        // Mapping is restored if new owner can't be granted
+        mock.push_error(
+            "setclientchannelgroup",
+            2568,
+            "insufficient client permissions",
+        );
+        handle(
+            &mut conn,
+            &mut storage,
+            &[1],
+            "uid",
+            5,
+            &message("!transfer bar"),
+        )
+        .await
+        .unwrap();
+        assert!(!mock
+            .take_commands()
+            .contains(&"setclientchannelgroup cgid=8 cid=20 cldbid=10".to_string()));
+        assert_eq!(
+            storage.get(&channel_key(10, "uid", 1)).await.unwrap(),
+            Some(20)
+        );
+        assert_eq!(storage.get(&channel_key(11, "uid", 1)).await.unwrap(), None);
+
+        handle(
+            &mut conn,
+            &mut storage,
+            &[1],
+            "uid",
+            5,
+            &message("!transfer bar"),
+        )
+        .await
+        .unwrap();
+        let commands = mock.take_commands();
+        assert!(commands.contains(&"setclientchannelgroup cgid=5 cid=20 cldbid=11".to_string()));
+        assert!(commands.contains(&"setclientchannelgroup cgid=8 cid=20 cldbid=10".to_string()));
+        assert_eq!(storage.get(&channel_key(10, "uid", 1)).await.unwrap(), None);
+        assert_eq!(
+            storage.get(&channel_key(11, "uid", 1)).await.unwrap(),
+            Some(20)
+        );
+    }
+}
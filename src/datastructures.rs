@@ -9,6 +9,7 @@ pub trait FromQueryString: for<'de> Deserialize<'de> {
 }
 
 pub trait FromJSON: for<'de> Deserialize<'de> {
+    #[allow(dead_code)]
     fn from_json(data: &str) -> anyhow::Result<Self>
     where
         Self: Sized,
@@ -141,7 +142,8 @@ pub mod client {
         client_database_id: i64,
         #[serde(deserialize_with = "from_str")]
         client_type: i64,
-        //client_unique_identifier: String,
+        #[serde(default)]
+        client_unique_identifier: String,
         client_nickname: String,
     }
 
@@ -159,8 +161,8 @@ pub mod client {
         pub fn client_type(&self) -> i64 {
             self.client_type
         }
-        pub fn client_unique_identifier(&self) -> String {
-            format!("{}", self.client_database_id)
+        pub fn client_unique_identifier(&self) -> &str {
+            &self.client_unique_identifier
         }
         pub fn client_nickname(&self) -> &str {
             &self.client_nickname
@@ -190,6 +192,205 @@ pub mod client {
     }
 }
 
+pub mod client_db_id {
+    use super::{from_str, FromJSON, FromQueryString};
+    use serde_derive::Deserialize;
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct ClientDatabaseId {
+        #[serde(deserialize_with = "from_str")]
+        cldbid: i64,
+    }
+
+    impl ClientDatabaseId {
+        pub fn cldbid(&self) -> i64 {
+            self.cldbid
+        }
+    }
+
+    impl FromQueryString for ClientDatabaseId {}
+    impl FromJSON for ClientDatabaseId {}
+}
+
+pub mod permission {
+    use super::{from_str, FromJSON, FromQueryString};
+    use serde_derive::Deserialize;
+
+    // permissionlist may contain group_id_end entries without permission, so fields are optional
+    #[derive(Clone, Debug, Default, Deserialize)]
+    pub struct PermissionInfo {
+        #[serde(default, deserialize_with = "from_str")]
+        permid: u64,
+        #[serde(default)]
+        permname: String,
+    }
+
+    impl PermissionInfo {
+        pub fn permid(&self) -> u64 {
+            self.permid
+        }
+        pub fn permname(&self) -> &str {
+            &self.permname
+        }
+    }
+
+    impl FromQueryString for PermissionInfo {}
+    impl FromJSON for PermissionInfo {}
+}
+
+pub mod channel_group {
+    use super::{from_str, FromJSON, FromQueryString};
+    use serde_derive::Deserialize;
+
+    #[derive(Clone, Debug, Default, Deserialize)]
+    pub struct ChannelGroup {
+        #[serde(deserialize_with = "from_str")]
+        cgid: i64,
+    }
+
+    impl ChannelGroup {
+        pub fn cgid(&self) -> i64 {
+            self.cgid
+        }
+    }
+
+    impl FromQueryString for ChannelGroup {}
+    impl FromJSON for ChannelGroup {}
+}
+
+pub mod notifies {
+    use super::{from_str, FromQueryString};
+    use serde_derive::Deserialize;
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct NotifyClientMovedView {
+        #[serde(deserialize_with = "from_str")]
+        ctid: i64,
+        #[serde(deserialize_with = "from_str")]
+        clid: i64,
+    }
+
+    impl NotifyClientMovedView {
+        pub fn channel_id(&self) -> i64 {
+            self.ctid
+        }
+        pub fn client_id(&self) -> i64 {
+            self.clid
+        }
+    }
+
+    impl FromQueryString for NotifyClientMovedView {}
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct NotifyTextMessage {
+        #[serde(deserialize_with = "from_str")]
+        targetmode: i64,
+        msg: String,
+        #[serde(deserialize_with = "from_str")]
+        invokerid: i64,
+        #[serde(default)]
+        invokername: String,
+    }
+
+    impl NotifyTextMessage {
+        /// 1 for private message, 2 for channel and 3 for server.
+        pub fn target_mode(&self) -> i64 {
+            self.targetmode
+        }
+        pub fn msg(&self) -> &str {
+            &self.msg
+        }
+        pub fn invoker_id(&self) -> i64 {
+            self.invokerid
+        }
+        pub fn invoker_name(&self) -> &str {
+            &self.invokername
+        }
+    }
+
+    impl FromQueryString for NotifyTextMessage {}
+
+    #[allow(dead_code)]
+    #[derive(Clone, Debug)]
+    pub enum Notifies {
+        ClientEnterView(Vec<NotifyClientMovedView>),
+        ClientMoved(Vec<NotifyClientMovedView>),
+        TextMessage(NotifyTextMessage),
+        Unknown(String),
+    }
+
+    impl Notifies {
+        pub fn is_notify(line: &str) -> bool {
+            line.trim().starts_with("notify")
+        }
+
+        fn parse_views(body: &str) -> anyhow::Result<Vec<NotifyClientMovedView>> {
+            body.split('|')
+                .map(NotifyClientMovedView::from_query)
+                .collect()
+        }
+
+        pub fn from_line(line: &str) -> anyhow::Result<Self> {
+            let line = line.trim();
+            let (event, body) = line.split_once(' ').unwrap_or((line, ""));
+            Ok(match event {
+                "notifycliententerview" => Self::ClientEnterView(Self::parse_views(body)?),
+                "notifyclientmoved" => Self::ClientMoved(Self::parse_views(body)?),
+                "notifytextmessage" => Self::TextMessage(NotifyTextMessage::from_query(body)?),
+                _ => Self::Unknown(line.to_string()),
+            })
+        }
+
+        /// Client views which entered or moved into any of `channels`.
+        pub fn entered(&self, channels: &[i64]) -> Vec<&NotifyClientMovedView> {
+            match self {
+                Self::ClientEnterView(views) | Self::ClientMoved(views) => views
+                    .iter()
+                    .filter(|view| channels.contains(&view.channel_id()))
+                    .collect(),
+                Self::TextMessage(_) | Self::Unknown(_) => vec![],
+            }
+        }
+
+        /// Private text message sent to us.
+        pub fn private_message(&self) -> Option<&NotifyTextMessage> {
+            match self {
+                Self::TextMessage(message) if message.target_mode() == 1 => Some(message),
+                _ => None,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::notifies::Notifies;
+
+        const TEST_STRING: &str =
+            "notifyclientmoved ctid=5 reasonid=0 clid=7|ctid=2 reasonid=0 clid=8";
+
+        #[test]
+        fn test() {
+            let result = Notifies::from_line(TEST_STRING).unwrap();
+            let entered = result.entered(&[5]);
+            assert_eq!(entered.len(), 1);
+            assert_eq!(entered[0].client_id(), 7);
+            assert!(Notifies::is_notify(TEST_STRING));
+            let result = Notifies::from_line(
+                "notifytextmessage targetmode=1 msg=!name\\sfoo target=1 invokerid=7 invokername=bar invokeruid=abc",
+            )
+            .unwrap();
+            let message = result.private_message().unwrap();
+            assert_eq!(message.msg(), "!name foo");
+            assert_eq!(message.invoker_id(), 7);
+            assert!(result.entered(&[5]).is_empty());
+            assert!(matches!(
+                Notifies::from_line("notifyserveredited reasonid=10").unwrap(),
+                Notifies::Unknown(_)
+            ));
+        }
+    }
+}
+
 pub mod query_status {
     use crate::datastructures::{QueryError, QueryResult};
     use anyhow::anyhow;
@@ -266,18 +467,23 @@ pub mod query_status {
 }
 
 pub mod server_info {
-    use super::{FromJSON, FromQueryString};
+    use super::{from_str, FromJSON, FromQueryString};
     use serde_derive::Deserialize;
 
     #[derive(Clone, Debug, Deserialize)]
     pub struct ServerInfo {
         virtualserver_unique_identifier: String,
+        #[serde(default, deserialize_with = "from_str")]
+        virtualserver_default_channel_group: i64,
     }
 
     impl ServerInfo {
         pub fn virtualserver_unique_identifier(&self) -> &str {
             &self.virtualserver_unique_identifier
         }
+        pub fn virtualserver_default_channel_group(&self) -> i64 {
+            self.virtualserver_default_channel_group
+        }
     }
 
     impl FromQueryString for ServerInfo {}
@@ -285,11 +491,15 @@ pub mod server_info {
 }
 
 pub mod config {
+    use crate::overrides;
+    use crate::template::DEFAULT_NAME_TEMPLATE;
+    use crate::transport::Endpoint;
     use anyhow::anyhow;
     use serde_derive::Deserialize;
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
     use std::fs::read_to_string;
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
 
     #[derive(Clone, Debug, Deserialize)]
     #[serde(untagged)]
@@ -309,25 +519,77 @@ pub mod config {
         }
     }
 
+    #[derive(Clone, Debug, Deserialize)]
+    #[serde(untagged)]
+    pub enum PermissionMap {
+        Id(Vec<(u64, i64)>),
+        Name(BTreeMap<String, i64>),
+    }
+
+    impl PermissionMap {
+        pub fn names(&self) -> Vec<&str> {
+            match self {
+                PermissionMap::Id(_) => vec![],
+                PermissionMap::Name(map) => map.keys().map(|k| k.as_str()).collect(),
+            }
+        }
+
+        pub fn resolve(
+            &self,
+            permissions: &HashMap<String, u64>,
+        ) -> anyhow::Result<Vec<(u64, i64)>> {
+            let unknown = self
+                .names()
+                .into_iter()
+                .filter(|name| !permissions.contains_key(*name))
+                .collect::<Vec<_>>();
+            if !unknown.is_empty() {
+                return Err(anyhow!("Unknown permission name: {}", unknown.join(", ")));
+            }
+            Ok(self.resolve_known(permissions))
+        }
+
+        /// Same as `resolve`, but unknown names are skipped instead of error.
+        pub fn resolve_known(&self, permissions: &HashMap<String, u64>) -> Vec<(u64, i64)> {
+            match self {
+                PermissionMap::Id(map) => map.clone(),
+                PermissionMap::Name(map) => map
+                    .iter()
+                    .filter_map(|(name, value)| permissions.get(name).map(|id| (*id, *value)))
+                    .collect(),
+            }
+        }
+    }
+
     #[derive(Clone, Debug, Deserialize)]
     pub struct Permission {
         channel_id: Integer,
-        map: Vec<(u64, i64)>,
+        map: PermissionMap,
     }
 
     impl Permission {
         pub fn channel_id(&self) -> &Integer {
             &self.channel_id
         }
-        pub fn map(&self) -> &Vec<(u64, i64)> {
+        pub fn map(&self) -> &PermissionMap {
             &self.map
         }
     }
 
+    #[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum QueryProtocol {
+        #[default]
+        Raw,
+        Ssh,
+    }
+
     #[derive(Clone, Debug, Deserialize)]
     pub struct RawQuery {
         server: Option<String>,
         port: Option<u16>,
+        #[serde(default)]
+        protocol: QueryProtocol,
         user: String,
         password: String,
     }
@@ -341,7 +603,10 @@ pub mod config {
             }
         }
         pub fn port(&self) -> u16 {
-            self.port.unwrap_or(10011)
+            self.port.unwrap_or(match self.protocol {
+                QueryProtocol::Raw => 10011,
+                QueryProtocol::Ssh => 10022,
+            })
         }
         pub fn user(&self) -> &str {
             &self.user
@@ -349,14 +614,239 @@ pub mod config {
         pub fn password(&self) -> &str {
             &self.password
         }
+        pub fn endpoint(&self) -> Endpoint {
+            match self.protocol {
+                QueryProtocol::Raw => Endpoint::Tcp {
+                    server: self.server(),
+                    port: self.port(),
+                },
+                QueryProtocol::Ssh => Endpoint::Ssh {
+                    server: self.server(),
+                    port: self.port(),
+                    user: self.user.clone(),
+                    password: self.password.clone(),
+                },
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct WebQuery {
+        server: String,
+        api_key: String,
+    }
+
+    impl WebQuery {
+        pub fn server(&self) -> &str {
+            &self.server
+        }
+        pub fn api_key(&self) -> &str {
+            &self.api_key
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum ChannelType {
+        Temporary,
+        SemiPermanent,
+        Permanent,
+    }
+
+    impl ChannelType {
+        pub fn flag(&self) -> &'static str {
+            match self {
+                ChannelType::Temporary => "channel_flag_temporary",
+                ChannelType::SemiPermanent => "channel_flag_semi_permanent",
+                ChannelType::Permanent => "channel_flag_permanent",
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, Default, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct ChannelProperties {
+        channel_codec: Option<u8>,
+        channel_codec_quality: Option<u8>,
+        channel_maxclients: Option<i64>,
+        channel_maxfamilyclients: Option<i64>,
+        channel_topic: Option<String>,
+        channel_description: Option<String>,
+        channel_needed_talk_power: Option<i64>,
+        channel_icon_id: Option<i64>,
+    }
+
+    impl ChannelProperties {
+        pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+            let mut v = vec![(
+                "channel_codec_quality",
+                self.channel_codec_quality.unwrap_or(6).to_string(),
+            )];
+            if let Some(codec) = self.channel_codec {
+                v.push(("channel_codec", codec.to_string()));
+            }
+            if let Some(max_clients) = self.channel_maxclients {
+                v.push(("channel_maxclients", max_clients.to_string()));
+                v.push(("channel_flag_maxclients_unlimited", "0".to_string()));
+            }
+            if let Some(max_family_clients) = self.channel_maxfamilyclients {
+                v.push(("channel_maxfamilyclients", max_family_clients.to_string()));
+                v.push(("channel_flag_maxfamilyclients_unlimited", "0".to_string()));
+                v.push(("channel_flag_maxfamilyclients_inherited", "0".to_string()));
+            }
+            if let Some(topic) = &self.channel_topic {
+                v.push(("channel_topic", topic.clone()));
+            }
+            if let Some(description) = &self.channel_description {
+                v.push(("channel_description", description.clone()));
+            }
+            if let Some(talk_power) = self.channel_needed_talk_power {
+                v.push(("channel_needed_talk_power", talk_power.to_string()));
+            }
+            if let Some(icon_id) = self.channel_icon_id {
+                v.push(("channel_icon_id", icon_id.to_string()));
+            }
+            v
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct ChannelOption {
+        id: i64,
+        #[serde(rename = "type")]
+        channel_type: Option<ChannelType>,
+        name: Option<String>,
+        #[serde(default)]
+        properties: ChannelProperties,
+    }
+
+    #[derive(Clone, Debug)]
+    pub enum MonitorChannel {
+        Id(i64),
+        Detail(ChannelOption),
+    }
+
+    impl MonitorChannel {
+        // Not use untagged enum here, it hides the real error (e.g. unknown channel property)
+        fn from_value(value: toml::Value) -> Result<Self, String> {
+            match value {
+                toml::Value::Integer(id) => Ok(Self::Id(id)),
+                value => value
+                    .try_into()
+                    .map(Self::Detail)
+                    .map_err(|e| format!("Invalid channel_id entry: {}", e)),
+            }
+        }
+    }
+
+    impl MonitorChannel {
+        pub fn id(&self) -> i64 {
+            match self {
+                MonitorChannel::Id(id) => *id,
+                MonitorChannel::Detail(option) => option.id,
+            }
+        }
+        pub fn channel_type(&self) -> Option<ChannelType> {
+            match self {
+                MonitorChannel::Id(_) => None,
+                MonitorChannel::Detail(option) => option.channel_type,
+            }
+        }
+        pub fn name_template(&self) -> &str {
+            match self {
+                MonitorChannel::Detail(ChannelOption {
+                    name: Some(name), ..
+                }) => name,
+                _ => DEFAULT_NAME_TEMPLATE,
+            }
+        }
+        pub fn properties(&self) -> ChannelProperties {
+            match self {
+                MonitorChannel::Id(_) => Default::default(),
+                MonitorChannel::Detail(option) => option.properties.clone(),
+            }
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    pub enum MonitorChannels {
+        Single(MonitorChannel),
+        Multiple(Vec<MonitorChannel>),
+    }
+
+    impl<'de> serde::Deserialize<'de> for MonitorChannels {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            match <toml::Value as serde::Deserialize>::deserialize(deserializer)? {
+                toml::Value::Array(values) => values
+                    .into_iter()
+                    .map(MonitorChannel::from_value)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Self::Multiple),
+                value => MonitorChannel::from_value(value).map(Self::Single),
+            }
+            .map_err(serde::de::Error::custom)
+        }
+    }
+
+    impl MonitorChannels {
+        fn to_vec(&self) -> Vec<MonitorChannel> {
+            match self {
+                MonitorChannels::Single(channel) => {
+                    vec![channel.clone()]
+                }
+                MonitorChannels::Multiple(channels) => channels.clone(),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum StorageType {
+        #[default]
+        Redis,
+        Sqlite,
+        Json,
+        Memory,
+    }
+
+    impl StorageType {
+        /// Used if `storage_path` is not set.
+        pub fn default_path(&self) -> PathBuf {
+            match self {
+                StorageType::Json => PathBuf::from("autochannel.json"),
+                _ => PathBuf::from("autochannel.db"),
+            }
+        }
+    }
+
+    impl FromStr for StorageType {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            Ok(match s {
+                "redis" => Self::Redis,
+                "sqlite" => Self::Sqlite,
+                "json" => Self::Json,
+                "memory" => Self::Memory,
+                _ => return Err(anyhow!("Unknown storage: {}", s)),
+            })
+        }
     }
 
     #[derive(Clone, Debug, Deserialize)]
     pub struct Server {
         server_id: Option<i64>,
-        channel_id: Integer,
+        channel_id: MonitorChannels,
         privilege_group_id: i64,
         redis_server: Option<String>,
+        storage: Option<StorageType>,
+        storage_path: Option<String>,
+        default_permission: Option<PermissionMap>,
+        permissions: Option<Vec<Permission>>,
     }
 
     impl Server {
@@ -364,11 +854,23 @@ pub mod config {
             self.server_id.unwrap_or(1)
         }
         pub fn channels(&self) -> Vec<i64> {
-            self.channel_id.to_vec()
+            self.channel_id.to_vec().iter().map(|v| v.id()).collect()
+        }
+        pub fn monitor_channels(&self) -> HashMap<i64, MonitorChannel> {
+            self.channel_id
+                .to_vec()
+                .into_iter()
+                .map(|v| (v.id(), v))
+                .collect()
         }
         pub fn privilege_group_id(&self) -> i64 {
             self.privilege_group_id
         }
+        pub fn default_permission(&self) -> PermissionMap {
+            self.default_permission
+                .clone()
+                .unwrap_or_else(|| PermissionMap::Id(vec![(133, 75)]))
+        }
         pub fn redis_server(&self) -> String {
             if let Some(server) = &self.redis_server {
                 server.clone()
@@ -376,6 +878,16 @@ pub mod config {
                 String::from("redis://127.0.0.1")
             }
         }
+        pub fn storage(&self) -> StorageType {
+            self.storage.unwrap_or_default()
+        }
+        pub fn storage_path(&self) -> PathBuf {
+            if let Some(path) = &self.storage_path {
+                PathBuf::from(path)
+            } else {
+                self.storage().default_path()
+            }
+        }
     }
 
     #[derive(Clone, Debug, Default, Deserialize)]
@@ -403,10 +915,14 @@ pub mod config {
         }
     }
 
-    #[derive(Clone, Debug, Deserialize)]
+    #[derive(Clone, Debug, Default, Deserialize)]
     pub struct Misc {
         interval: Option<u64>,
         systemd: Option<bool>,
+        event_driven: Option<bool>,
+        fallback_interval: Option<u64>,
+        watch_config: Option<bool>,
+        keepalive_interval: Option<u64>,
     }
 
     impl Misc {
@@ -417,60 +933,337 @@ pub mod config {
         pub fn systemd(&self) -> bool {
             self.systemd.unwrap_or(false)
         }
+
+        pub fn event_driven(&self) -> bool {
+            self.event_driven.unwrap_or(true)
+        }
+
+        pub fn fallback_interval(&self) -> u64 {
+            self.fallback_interval.unwrap_or(30000)
+        }
+
+        pub fn watch_config(&self) -> bool {
+            self.watch_config.unwrap_or(false)
+        }
+
+        pub fn keepalive_interval(&self) -> u64 {
+            self.keepalive_interval.unwrap_or(240)
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct Cleanup {
+        grace_period: Option<u64>,
+        interval: Option<u64>,
+    }
+
+    impl Cleanup {
+        pub fn grace_period(&self) -> u64 {
+            self.grace_period.unwrap_or(300)
+        }
+        pub fn interval(&self) -> u64 {
+            self.interval.unwrap_or(60)
+        }
+    }
+
+    fn non_empty<'de, D>(deserializer: D) -> Result<String, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        if s.trim().is_empty() {
+            return Err(serde::de::Error::custom("Value can't be empty"));
+        }
+        Ok(s)
+    }
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct Admin {
+        listen: Option<String>,
+        #[serde(deserialize_with = "non_empty")]
+        token: String,
+    }
+
+    impl Admin {
+        pub fn listen(&self) -> &str {
+            self.listen.as_deref().unwrap_or("127.0.0.1:9990")
+        }
+        pub fn token(&self) -> &str {
+            &self.token
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct Metrics {
+        listen: Option<String>,
+    }
+
+    impl Metrics {
+        pub fn listen(&self) -> &str {
+            self.listen.as_deref().unwrap_or("127.0.0.1:9991")
+        }
+    }
+
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct Audit {
+        path: Option<String>,
+    }
+
+    impl Audit {
+        pub fn path(&self) -> PathBuf {
+            PathBuf::from(self.path.as_deref().unwrap_or("audit.jsonl"))
+        }
+    }
+
+    // `[server]` for single virtual server, `[[server]]` for multiple
+    #[derive(Clone, Debug)]
+    pub struct Servers(Vec<Server>);
+
+    impl<'de> serde::Deserialize<'de> for Servers {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            let servers = match <toml::Value as serde::Deserialize>::deserialize(deserializer)? {
+                toml::Value::Array(values) => values
+                    .into_iter()
+                    .map(<Server as serde::Deserialize>::deserialize)
+                    .collect::<Result<Vec<_>, _>>(),
+                value => <Server as serde::Deserialize>::deserialize(value).map(|v| vec![v]),
+            }
+            .map_err(serde::de::Error::custom)?;
+            if servers.is_empty() {
+                return Err(serde::de::Error::custom("At least one server is required"));
+            }
+            Ok(Self(servers))
+        }
     }
 
     #[derive(Clone, Debug, Deserialize)]
     pub struct Config {
-        server: Server,
+        server: Servers,
+        #[serde(default)]
         misc: Misc,
         custom_message: Option<Message>,
         permissions: Option<Vec<Permission>>,
-        raw_query: RawQuery,
+        cleanup: Option<Cleanup>,
+        admin: Option<Admin>,
+        metrics: Option<Metrics>,
+        audit: Option<Audit>,
+        raw_query: Option<RawQuery>,
+        web_query: Option<WebQuery>,
     }
 
     impl Config {
+        // Process wide options (like storage) are read from first server
         pub fn server(&self) -> &Server {
-            &self.server
+            &self.server.0[0]
+        }
+        pub fn servers(&self) -> &[Server] {
+            &self.server.0
+        }
+        pub fn server_by_id(&self, server_id: i64) -> Option<&Server> {
+            self.servers()
+                .iter()
+                .find(|server| server.server_id() == server_id)
         }
         pub fn misc(&self) -> &Misc {
             &self.misc
         }
-        pub fn raw_query(&self) -> &RawQuery {
+        pub fn raw_query(&self) -> &Option<RawQuery> {
             &self.raw_query
         }
+        pub fn web_query(&self) -> &Option<WebQuery> {
+            &self.web_query
+        }
         pub fn message(&self) -> Message {
             self.custom_message.clone().unwrap_or_default()
         }
-        pub fn channel_permissions(&self) -> HashMap<i64, Vec<(u64, i64)>> {
-            let mut m = Default::default();
-            match &self.permissions {
-                None => m,
-                Some(permissions) => {
-                    for permission in permissions {
-                        match permission.channel_id() {
-                            Integer::Single(channel_id) => {
-                                m.insert(*channel_id, permission.map().clone());
-                            }
-                            Integer::Multiple(channel_ids) => {
-                                for channel_id in channel_ids {
-                                    m.insert(*channel_id, permission.map().clone());
-                                }
-                            }
-                        }
-                    }
-                    m
+        pub fn cleanup(&self) -> &Option<Cleanup> {
+            &self.cleanup
+        }
+        pub fn admin(&self) -> &Option<Admin> {
+            &self.admin
+        }
+        pub fn metrics(&self) -> &Option<Metrics> {
+            &self.metrics
+        }
+        pub fn audit(&self) -> &Option<Audit> {
+            &self.audit
+        }
+        // Top level permissions are shared by all servers, server's own permissions take precedence
+        fn server_permissions<'a>(
+            &'a self,
+            server: &'a Server,
+        ) -> impl Iterator<Item = &'a Permission> {
+            self.permissions
+                .iter()
+                .flatten()
+                .chain(server.permissions.iter().flatten())
+        }
+        pub fn permission_names<'a>(&'a self, server: &'a Server) -> Vec<&'a str> {
+            let mut v = Vec::new();
+            if let Some(default_permission) = &server.default_permission {
+                v.extend(default_permission.names());
+            }
+            for permission in self.server_permissions(server) {
+                v.extend(permission.map().names());
+            }
+            v
+        }
+        pub fn channel_permissions(
+            &self,
+            server: &Server,
+            permissions: &HashMap<String, u64>,
+        ) -> anyhow::Result<HashMap<i64, Vec<(u64, i64)>>> {
+            self.collect_channel_permissions(server, |map| map.resolve(permissions))
+        }
+        /// Same as `channel_permissions`, but unknown names are skipped instead of error.
+        pub fn known_channel_permissions(
+            &self,
+            server: &Server,
+            permissions: &HashMap<String, u64>,
+        ) -> HashMap<i64, Vec<(u64, i64)>> {
+            self.collect_channel_permissions(server, |map| Ok(map.resolve_known(permissions)))
+                .unwrap()
+        }
+        fn collect_channel_permissions<F>(
+            &self,
+            server: &Server,
+            resolve: F,
+        ) -> anyhow::Result<HashMap<i64, Vec<(u64, i64)>>>
+        where
+            F: Fn(&PermissionMap) -> anyhow::Result<Vec<(u64, i64)>>,
+        {
+            let mut m = HashMap::new();
+            for permission in self.server_permissions(server) {
+                let map = resolve(permission.map())?;
+                for channel_id in permission.channel_id().to_vec() {
+                    m.insert(channel_id, map.clone());
                 }
             }
+            Ok(m)
         }
     }
 
     impl TryFrom<&Path> for Config {
         type Error = anyhow::Error;
 
+        // Layered from defaults, then configure file, then environment variables
         fn try_from(path: &Path) -> Result<Self, Self::Error> {
-            let content = read_to_string(path).map_err(|e| anyhow!("Read error: {:?}", e))?;
+            let content = match read_to_string(path) {
+                Ok(content) => content,
+                // Configure file is optional if everything is given by environment variables
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound && overrides::has_env() => {
+                    String::new()
+                }
+                Err(e) => return Err(anyhow!("Read error: {:?}", e)),
+            };
+
+            let mut value: toml::Value =
+                toml::from_str(&content).map_err(|e| anyhow!("Deserialize toml error: {:?}", e))?;
+            let env_changed = overrides::apply_env(&mut value, std::env::vars())?;
+            let file_changed = overrides::resolve_files(&mut value)?;
+            // Deserialize from text if possible, which reports line of error
+            let config: Self = if env_changed || file_changed {
+                value.try_into()
+            } else {
+                toml::from_str(&content)
+            }
+            .map_err(|e| anyhow!("Deserialize toml error: {:?}", e))?;
+            if config.raw_query.is_none() && config.web_query.is_none() {
+                return Err(anyhow!("Either raw_query or web_query section is required"));
+            }
+            let mut server_ids = config
+                .servers()
+                .iter()
+                .map(|server| server.server_id())
+                .collect::<Vec<_>>();
+            server_ids.sort_unstable();
+            if let Some(ids) = server_ids.windows(2).find(|ids| ids[0] == ids[1]) {
+                return Err(anyhow!("Duplicate server_id: {}", ids[0]));
+            }
+            Ok(config)
+        }
+    }
 
-            toml::from_str(&content).map_err(|e| anyhow!("Deserialize toml error: {:?}", e))
+    #[cfg(test)]
+    mod test {
+        use crate::datastructures::config::{ChannelType, Config};
+
+        const TEST_CONFIG: &str = r#"
+[server]
+channel_id = [1, { id = 2, type = "semi-permanent", properties = { channel_maxclients = 5 } }]
+privilege_group_id = 5
+
+[misc]
+
+[raw_query]
+user = "serveradmin"
+password = "114514"
+"#;
+
+        #[test]
+        fn test() {
+            let config: Config = toml::from_str(TEST_CONFIG).unwrap();
+            let channels = config.server().monitor_channels();
+            assert_eq!(channels[&1].channel_type(), None);
+            assert_eq!(
+                channels[&2].channel_type(),
+                Some(ChannelType::SemiPermanent)
+            );
+            assert!(channels[&2]
+                .properties()
+                .to_pairs()
+                .contains(&("channel_maxclients", "5".to_string())));
+
+            let err = toml::from_str::<Config>(
+                &TEST_CONFIG.replace("channel_maxclients", "channel_max_clients"),
+            )
+            .unwrap_err();
+            assert!(err.to_string().contains("channel_max_clients"));
+
+            assert!(
+                toml::from_str::<Config>(&format!("{}\n[admin]\ntoken = \" \"", TEST_CONFIG))
+                    .is_err()
+            );
+
+            let config: Config = toml::from_str(&format!(
+                "{}\n[[permissions]]\nchannel_id = 1\nmap = {{ i_channel_needed_permission_modify_power = 75 }}",
+                TEST_CONFIG
+            ))
+            .unwrap();
+            let server = config.server();
+            assert_eq!(
+                config.permission_names(server),
+                vec!["i_channel_needed_permission_modify_power"]
+            );
+            assert!(config
+                .channel_permissions(server, &Default::default())
+                .is_err());
+            let permissions = [("i_channel_needed_permission_modify_power".to_string(), 86)].into();
+            assert_eq!(
+                config.channel_permissions(server, &permissions).unwrap()[&1],
+                vec![(86, 75)]
+            );
+
+            let config: Config = toml::from_str(&TEST_CONFIG.replace(
+                "[server]",
+                "[[server]]\nserver_id = 2\nchannel_id = 3\nprivilege_group_id = 5\n\n[[server.permissions]]\nchannel_id = 1\nmap = [[86, 60]]\n\n[[server]]",
+            ))
+            .unwrap();
+            assert_eq!(config.servers().len(), 2);
+            assert_eq!(config.server_by_id(2).unwrap().channels(), vec![3]);
+            assert_eq!(
+                config
+                    .channel_permissions(config.server_by_id(2).unwrap(), &permissions)
+                    .unwrap()[&1],
+                vec![(86, 60)]
+            );
+            assert!(config
+                .channel_permissions(config.server_by_id(1).unwrap(), &permissions)
+                .unwrap()
+                .is_empty());
         }
     }
 }
@@ -528,9 +1321,13 @@ mod status_result {
 }
 
 pub use channel::Channel;
+pub use channel_group::ChannelGroup;
 pub use client::Client;
+pub use client_db_id::ClientDatabaseId;
 pub use config::Config;
 pub use create_channel::CreateChannel;
+pub use notifies::{Notifies, NotifyTextMessage};
+pub use permission::PermissionInfo;
 pub use query_status::{QueryStatus, WebQueryStatus};
 use serde::Deserialize;
 use serde_json::Value;
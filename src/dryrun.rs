@@ -0,0 +1,237 @@
+use crate::datastructures::config::{ChannelProperties, ChannelType};
+use crate::datastructures::{
+    Channel, ChannelGroup, Client, ClientDatabaseId, CreateChannel, Notifies, PermissionInfo,
+    QueryResult, ServerInfo, WhoAmI,
+};
+use crate::query::QueryConn;
+use crate::storage::Storage;
+use async_trait::async_trait;
+use log::info;
+use std::time::{Duration, Instant};
+
+/// Pass queries to wrapped connection, only log commands which change server state.
+pub struct DryRunConn(Box<dyn QueryConn>);
+
+impl DryRunConn {
+    pub fn new(conn: Box<dyn QueryConn>) -> Self {
+        Self(conn)
+    }
+}
+
+#[async_trait]
+impl QueryConn for DryRunConn {
+    fn support_notifies(&self) -> bool {
+        self.0.support_notifies()
+    }
+
+    async fn ensure_connected(&mut self) {
+        self.0.ensure_connected().await
+    }
+
+    async fn wait_notifies(&mut self, timeout: Duration) -> anyhow::Result<Vec<Notifies>> {
+        self.0.wait_notifies(timeout).await
+    }
+
+    fn take_reconnected(&mut self) -> bool {
+        self.0.take_reconnected()
+    }
+
+    async fn register_notifies(&mut self) -> QueryResult<()> {
+        self.0.register_notifies().await
+    }
+
+    fn last_active(&self) -> Instant {
+        self.0.last_active()
+    }
+
+    async fn keepalive(&mut self) -> QueryResult<()> {
+        self.0.keepalive().await
+    }
+
+    async fn who_am_i(&mut self) -> QueryResult<WhoAmI> {
+        self.0.who_am_i().await
+    }
+
+    async fn send_text_message(&mut self, clid: i64, text: &str) -> QueryResult<()> {
+        info!("[dry-run] Would send {:?} to client {}", text, clid);
+        Ok(())
+    }
+
+    async fn query_server_info(&mut self) -> QueryResult<ServerInfo> {
+        self.0.query_server_info().await
+    }
+
+    async fn query_channels(&mut self) -> QueryResult<Vec<Channel>> {
+        self.0.query_channels().await
+    }
+
+    async fn delete_channel(&mut self, cid: i64, force: bool) -> QueryResult<()> {
+        info!("[dry-run] Would delete channel {} (force: {})", cid, force);
+        Ok(())
+    }
+
+    // Channel id 0 stands for the channel which would be created
+    async fn create_channel(
+        &mut self,
+        name: &str,
+        pid: i64,
+        channel_type: Option<ChannelType>,
+        properties: &ChannelProperties,
+    ) -> QueryResult<Option<CreateChannel>> {
+        info!(
+            "[dry-run] Would create {:?} channel {:?} under {} with {:?}",
+            channel_type,
+            name,
+            pid,
+            properties.to_pairs()
+        );
+        Ok(Some(CreateChannel::default()))
+    }
+
+    async fn edit_channel(&mut self, cid: i64, properties: &[(&str, String)]) -> QueryResult<()> {
+        info!("[dry-run] Would edit channel {} with {:?}", cid, properties);
+        Ok(())
+    }
+
+    async fn query_clients(&mut self) -> QueryResult<Vec<Client>> {
+        self.0.query_clients().await
+    }
+
+    async fn query_client_database_id(&mut self, uid: &str) -> QueryResult<ClientDatabaseId> {
+        self.0.query_client_database_id(uid).await
+    }
+
+    async fn kick_client_from_channel(&mut self, clid: i64, reason: &str) -> QueryResult<()> {
+        info!("[dry-run] Would kick client {} ({})", clid, reason);
+        Ok(())
+    }
+
+    async fn move_client_to_channel(&mut self, clid: i64, target_channel: i64) -> QueryResult<()> {
+        info!(
+            "[dry-run] Would move client {} to channel {}",
+            clid, target_channel
+        );
+        Ok(())
+    }
+
+    async fn set_client_channel_group(
+        &mut self,
+        client_database_id: i64,
+        channel_id: i64,
+        group_id: i64,
+    ) -> QueryResult<()> {
+        info!(
+            "[dry-run] Would set channel group {} of client {} in channel {}",
+            group_id, client_database_id, channel_id
+        );
+        Ok(())
+    }
+
+    async fn add_channel_permission(
+        &mut self,
+        target_channel: i64,
+        permissions: &[(u64, i64)],
+    ) -> QueryResult<()> {
+        info!(
+            "[dry-run] Would add permissions {:?} to channel {}",
+            permissions, target_channel
+        );
+        Ok(())
+    }
+
+    async fn query_channel_groups(&mut self) -> QueryResult<Vec<ChannelGroup>> {
+        self.0.query_channel_groups().await
+    }
+
+    async fn query_permission_list(&mut self) -> QueryResult<Vec<PermissionInfo>> {
+        self.0.query_permission_list().await
+    }
+
+    async fn logout(&mut self) -> QueryResult<()> {
+        self.0.logout().await
+    }
+}
+
+/// Read from wrapped storage, skip every write.
+pub struct DryRunStorage(Box<dyn Storage>);
+
+impl DryRunStorage {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        Self(storage)
+    }
+}
+
+#[async_trait]
+impl Storage for DryRunStorage {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>> {
+        self.0.get(key).await
+    }
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()> {
+        info!("[dry-run] Would set {} to {}", key, cid);
+        Ok(())
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        info!("[dry-run] Would delete {}", key);
+        Ok(())
+    }
+
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool> {
+        info!("[dry-run] Would rename {} to {}", from, to);
+        Ok(true)
+    }
+
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>> {
+        self.0.keys(server_id).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dryrun::{DryRunConn, DryRunStorage};
+    use crate::mock::MockServer;
+    use crate::query::QueryConn;
+    use crate::socketlib::SocketConn;
+    use crate::storage::{channel_key, MemoryStorage, Storage};
+    use crate::transfer;
+
+    #[tokio::test]
+    async fn test() {
+        let mock = MockServer::start().await;
+        mock.set_reply(
+            "clientlist",
+            "clid=5 cid=1 client_database_id=10 client_nickname=foo client_type=0",
+        );
+        let mut conn = DryRunConn::new(Box::new(
+            SocketConn::connect(&mock.endpoint()).await.unwrap(),
+        ));
+        conn.query_clients().await.unwrap();
+        conn.move_client_to_channel(5, 20).await.unwrap();
+        conn.set_client_channel_group(10, 20, 5).await.unwrap();
+        assert_eq!(mock.take_commands(), vec!["clientlist -uid".to_string()]);
+
+        let mut storage = DryRunStorage::new(Box::<MemoryStorage>::default());
+        storage.set("key", 20).await.unwrap();
+        assert_eq!(storage.get("key").await.unwrap(), None);
+
+        // Transfer neither grants new owner nor moves mapping
+        let mut memory = MemoryStorage::default();
+        let key = channel_key(10, "uid", 1);
+        memory.set(&key, 20).await.unwrap();
+        let mut storage = DryRunStorage::new(Box::new(memory));
+        mock.set_reply(
+            "clientlist",
+            "clid=5 cid=20 client_database_id=10 client_nickname=foo client_type=0|clid=6 cid=20 client_database_id=11 client_nickname=bar client_type=0",
+        );
+        transfer::transfer(&mut conn, &mut storage, "uid", 5, &key, 20, "bar")
+            .await
+            .unwrap();
+        assert!(!mock
+            .take_commands()
+            .iter()
+            .any(|command| command.starts_with("setclientchannelgroup")));
+        assert_eq!(storage.get(&key).await.unwrap(), Some(20));
+        assert_eq!(storage.get(&channel_key(11, "uid", 1)).await.unwrap(), None);
+    }
+}
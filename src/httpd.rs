@@ -0,0 +1,37 @@
+use anyhow::anyhow;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response};
+use log::{error, info};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+
+/// Bind `listen` before anything starts, so startup fails if address is not usable,
+/// returned future serves every request by `handler`. `name` is used in logs and errors.
+pub fn bind<F, R>(
+    name: &'static str,
+    listen: &str,
+    handler: F,
+) -> anyhow::Result<impl Future<Output = anyhow::Result<()>>>
+where
+    F: Fn(Request<Body>) -> R + Clone + Send + 'static,
+    R: Future<Output = Result<Response<Body>, Infallible>> + Send + 'static,
+{
+    let addr: SocketAddr = listen
+        .parse()
+        .map_err(|e| anyhow!("Invalid {} listen address {}: {:?}", name, listen, e))?;
+    let service = make_service_fn(move |_| {
+        let handler = handler.clone();
+        async move { Ok::<_, Infallible>(service_fn(handler)) }
+    });
+    let server = hyper::Server::try_bind(&addr)
+        .map_err(|e| anyhow!("Bind {} to {} error: {:?}", name, addr, e))?
+        .serve(service);
+    info!("{} listen on {}", name, addr);
+    Ok(async move {
+        server.await.map_err(|e| {
+            error!("{} exited: {:?}", name, e);
+            anyhow!("{} error: {:?}", name, e)
+        })
+    })
+}
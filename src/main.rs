@@ -1,25 +1,58 @@
+mod admin;
+mod audit;
+mod cleanup;
+mod cli;
+mod codec;
+mod commands;
 mod datastructures;
+mod dryrun;
+mod httpd;
+mod metrics;
+#[cfg(test)]
+mod mock;
+mod overrides;
+mod query;
+mod reload;
 mod socketlib;
+mod storage;
+mod systemd;
+mod template;
+mod transfer;
+mod transport;
+mod validate;
+mod webquery;
 
-use crate::datastructures::Config;
+use crate::admin::AdminReceiver;
+use crate::audit::{Action, Record};
+use crate::cleanup::Cleaner;
+use crate::datastructures::config::{Message, MonitorChannel, Server};
+use crate::datastructures::{Config, WhoAmI};
+use crate::dryrun::{DryRunConn, DryRunStorage};
+use crate::metrics::{MeteredStorage, METRICS};
+use crate::query::QueryConn;
+use crate::reload::config_reloader;
 use crate::socketlib::SocketConn;
+use crate::storage::{channel_key, SharedStorage, Storage};
+use crate::template::NameTemplate;
+use crate::validate::validate;
+use crate::webquery::WebQueryConn;
 use anyhow::anyhow;
 use clap::{arg, Command};
 use log::{debug, error, info, warn};
 use once_cell::sync::OnceCell;
-use redis::AsyncCommands;
 use std::collections::HashMap;
 use std::hint::unreachable_unchecked;
-use std::path::Path;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 
-static MSG_CHANNEL_NOT_FOUND: OnceCell<String> = OnceCell::new();
-static MSG_CREATE_CHANNEL: OnceCell<String> = OnceCell::new();
-static MSG_MOVE_TO_CHANNEL: OnceCell<String> = OnceCell::new();
 static SYSTEMD_MODE: OnceCell<bool> = OnceCell::new();
 const SYSTEMD_MODE_RETRIE_TIMES: u32 = 3;
+const CHANNEL_NAME_RETRIE_TIMES: usize = 100;
 
-async fn try_init_connection(config: &Config, sid: i64) -> anyhow::Result<SocketConn> {
+async fn try_init_connection(config: &Config, sid: i64) -> anyhow::Result<Box<dyn QueryConn>> {
     let retries = if *SYSTEMD_MODE.get().unwrap() {
         debug!("Systemd mode is present, will retry if connection failed.");
         SYSTEMD_MODE_RETRIE_TIMES
@@ -42,217 +75,652 @@ async fn try_init_connection(config: &Config, sid: i64) -> anyhow::Result<Socket
     unsafe { unreachable_unchecked() }
 }
 
-async fn init_connection(config: &Config, sid: i64) -> anyhow::Result<SocketConn> {
-    let cfg = config.raw_query();
-    let mut conn = SocketConn::connect(&cfg.server(), cfg.port()).await?;
-    conn.login(cfg.user(), &cfg.password())
-        .await
-        .map_err(|e| anyhow!("Login failed. {:?}", e))?;
+async fn init_connection(config: &Config, sid: i64) -> anyhow::Result<Box<dyn QueryConn>> {
+    // raw_query has higher priority than web_query
+    let cfg = match config.raw_query() {
+        Some(cfg) => cfg,
+        None => {
+            let cfg = config
+                .web_query()
+                .as_ref()
+                .ok_or_else(|| anyhow!("Either raw_query or web_query section is required"))?;
+            return Ok(Box::new(
+                WebQueryConn::connect(cfg.server(), cfg.api_key(), sid).await?,
+            ));
+        }
+    };
+    let endpoint = cfg.endpoint();
+    let mut conn = SocketConn::connect(&endpoint).await?;
+    if endpoint.need_login() {
+        conn.login(cfg.user(), cfg.password())
+            .await
+            .map_err(|e| anyhow!("Login failed. {:?}", e))?;
+    }
 
     conn.select_server(sid)
         .await
         .map_err(|e| anyhow!("Select server id failed: {:?}", e))?;
 
-    Ok(conn)
+    Ok(Box::new(conn))
 }
 
-async fn observer(
-    conn: SocketConn,
-    monitor_channels: Vec<i64>,
-    privilege_group: i64,
-    redis_server: String,
-    interval: u64,
-    channel_permissions: HashMap<i64, Vec<(u64, i64)>>,
-) -> anyhow::Result<()> {
-    let (sender, receiver) = tokio::sync::oneshot::channel();
-
-    let staff_handler = tokio::spawn(staff(
-        conn,
-        monitor_channels,
-        privilege_group,
-        redis_server,
-        interval,
-        receiver,
-        channel_permissions,
-    ));
+// Storage is shared by all servers, options of first server are used
+async fn open_storage(config: &Config) -> anyhow::Result<Box<dyn Storage>> {
+    let storage = storage::open(
+        config.server().storage(),
+        &config.server().redis_server(),
+        &config.server().storage_path(),
+    )
+    .await?;
+    Ok(Box::new(MeteredStorage::new(storage)))
+}
+
+async fn observer(config: Config, path: PathBuf, dry_run: bool) -> anyhow::Result<()> {
+    let (shutdown_sender, shutdown_receiver) = tokio::sync::watch::channel(false);
+    let (config_sender, config_receiver) = tokio::sync::watch::channel(config.clone());
+
+    let mut storage = open_storage(&config).await?;
+    if dry_run {
+        storage = Box::new(DryRunStorage::new(storage));
+    }
+    let storage: SharedStorage = Arc::new(Mutex::new(storage));
+
+    // Check every server before start, so all problems are reported at once
+    let mut conns = Vec::new();
+    let mut problems = Vec::new();
+    for server in config.servers() {
+        let mut conn = try_init_connection(&config, server.server_id()).await?;
+        problems.extend(
+            validate(conn.as_mut(), &config, server)
+                .await?
+                .into_iter()
+                .map(|problem| format!("Server {}: {}", server.server_id(), problem)),
+        );
+        conns.push((server, conn));
+    }
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "Found {} problem(s) in configure file:\n{}",
+            problems.len(),
+            problems.join("\n")
+        ));
+    }
+
+    let mut admin_senders = HashMap::new();
+    let mut admin_receivers = Vec::new();
+    for server in config.servers() {
+        let (admin_sender, admin_receiver) = tokio::sync::mpsc::channel(16);
+        admin_senders.insert(server.server_id(), admin_sender);
+        admin_receivers.push(admin_receiver);
+    }
+    // Bind before any server starts, unusable address fails startup
+    let admin = config
+        .admin()
+        .as_ref()
+        .map(|admin| admin::bind(admin, admin_senders))
+        .transpose()?
+        .map(tokio::spawn);
+    let metrics = config
+        .metrics()
+        .as_ref()
+        .map(|metrics| metrics::bind(metrics.listen()))
+        .transpose()?
+        .map(tokio::spawn);
+
+    let mut staffs = JoinSet::new();
+    for ((server, mut conn), admin_receiver) in conns.into_iter().zip(admin_receivers) {
+        if dry_run {
+            conn = Box::new(DryRunConn::new(conn));
+        }
+        let server_id = server.server_id();
+        let handler = tokio::spawn(staff(
+            conn,
+            config.clone(),
+            server_id,
+            storage.clone(),
+            shutdown_receiver.clone(),
+            config_receiver.clone(),
+            admin_receiver,
+        ));
+        // Wrapped, so panic of staff is reported with its server
+        staffs.spawn(async move { (server_id, handler.await) });
+    }
+
+    let notifier = systemd::Notifier::from_env()
+        .map_err(|e| error!("Connect to systemd notify socket error: {:?}", e))
+        .ok()
+        .flatten()
+        .map(Arc::new);
+    let notify = notifier.clone().map(|notifier| {
+        tokio::spawn(systemd::serve(
+            notifier,
+            config
+                .servers()
+                .iter()
+                .map(|server| server.server_id())
+                .collect(),
+            systemd::watchdog_timeout(),
+        ))
+    });
+
+    let watch_config = config.misc().watch_config();
+    let reloader = tokio::spawn(async move {
+        if let Err(e) = config_reloader(path, config_sender, watch_config).await {
+            error!("Configure reloader exited, hot reload is disabled: {:?}", e);
+        }
+    });
+
+    // Servers run independently, error of one server does not stop others
+    let staffs = async {
+        let mut ret = Ok(());
+        while let Some(Ok((server_id, joined))) = staffs.join_next().await {
+            let e = match joined {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e,
+                Err(e) => anyhow!("Server task failed: {:?}", e),
+            };
+            error!("Server {} exited with error: {:?}", server_id, e);
+            ret = Err(e);
+        }
+        ret
+    };
 
     tokio::select! {
         _ = async {
             tokio::signal::ctrl_c().await.unwrap();
             info!("Recv SIGINT, send signal to thread.");
-            sender.send(true).unwrap();
+            shutdown_sender.send(true).unwrap();
             tokio::signal::ctrl_c().await.unwrap();
             error!("Force exit program.");
             std::process::exit(137);
         } => {
         }
-        ret = staff_handler => {
-            ret??
+        ret = staffs => {
+            ret?
         }
     }
+    reloader.abort();
+    for handler in [admin, metrics].into_iter().flatten() {
+        handler.abort();
+    }
+    if let Some((notify, notifier)) = notify.zip(notifier) {
+        notify.abort();
+        notifier.notify("STOPPING=1").ok();
+    }
 
     Ok(())
 }
 
-async fn staff(
-    mut conn: SocketConn,
+struct StaffConfig {
     monitor_channels: Vec<i64>,
+    channel_options: HashMap<i64, MonitorChannel>,
     privilege_group: i64,
-    redis_server: String,
-    interval: u64,
-    mut receiver: tokio::sync::oneshot::Receiver<bool>,
     channel_permissions: HashMap<i64, Vec<(u64, i64)>>,
-) -> anyhow::Result<()> {
-    info!(
-        "Interval is: {}, version: {}",
-        interval,
-        env!("CARGO_PKG_VERSION")
-    );
+    default_permission: Vec<(u64, i64)>,
+    message: Message,
+    interval: u64,
+    keepalive_interval: u64,
+}
 
-    let redis = redis::Client::open(redis_server)
-        .map_err(|e| anyhow!("Connect redis server error! {:?}", e))?;
-    let mut redis_conn = redis
-        .get_async_connection()
-        .await
-        .map_err(|e| anyhow!("Get redis connection error: {:?}", e))?;
+impl StaffConfig {
+    async fn load(
+        conn: &mut dyn QueryConn,
+        config: &Config,
+        server: &Server,
+        event_driven: bool,
+    ) -> anyhow::Result<Self> {
+        let permissions = if config.permission_names(server).is_empty() {
+            Default::default()
+        } else {
+            conn.query_permission_list()
+                .await
+                .map_err(|e| anyhow!("Query permission list error: {:?}", e))?
+                .into_iter()
+                .filter(|permission| !permission.permname().is_empty())
+                .map(|permission| (permission.permname().to_string(), permission.permid()))
+                .collect()
+        };
 
-    let who_am_i = conn
-        .who_am_i()
-        .await
-        .map_err(|e| anyhow!("Whoami failed: {:?}", e))?;
+        Ok(Self {
+            monitor_channels: server.channels(),
+            channel_options: server.monitor_channels(),
+            privilege_group: server.privilege_group_id(),
+            channel_permissions: config.channel_permissions(server, &permissions)?,
+            default_permission: server.default_permission().resolve(&permissions)?,
+            message: config.message(),
+            interval: if event_driven {
+                config.misc().fallback_interval()
+            } else {
+                config.misc().interval()
+            },
+            keepalive_interval: config.misc().keepalive_interval(),
+        })
+    }
+}
 
-    let server_info = conn
-        .query_server_info()
+/// Create channel for (or move back) clients in monitored channels,
+/// return true if next sweep should start immediately.
+async fn sweep_clients(
+    conn: &mut dyn QueryConn,
+    storage: &mut dyn Storage,
+    current: &StaffConfig,
+    who_am_i: &WhoAmI,
+    server_id: &str,
+) -> anyhow::Result<bool> {
+    let mut skip_sleep = false;
+    let clients = match conn
+        .query_clients()
         .await
-        .map_err(|e| anyhow!("Query server info error: {:?}", e))?;
+        .map_err(|e| error!("Got error while query clients: {:?}", e))
+    {
+        Ok(clients) => clients,
+        Err(_) => return Ok(false),
+    };
 
-    info!("Connected: {}", who_am_i.clid());
+    METRICS.set_monitored_clients(
+        server_id,
+        clients
+            .iter()
+            .filter(|client| {
+                client.client_database_id() != who_am_i.cldbid()
+                    && client.client_type() != 1
+                    && current.monitor_channels.contains(&client.channel_id())
+            })
+            .count(),
+    );
 
-    let mut skip_sleep = false;
-    loop {
-        if !skip_sleep {
-            //std::thread::sleep(Duration::from_millis(interval));
-            if tokio::time::timeout(Duration::from_millis(interval), &mut receiver)
-                .await
-                .is_ok()
-            {
-                info!("Exit!");
-                break;
-            }
-        } else {
-            skip_sleep = false;
-        }
-        let clients = match conn
-            .query_clients()
-            .await
-            .map_err(|e| error!("Got error while query clients: {:?}", e))
+    'outer: for client in clients {
+        if client.client_database_id() == who_am_i.cldbid()
+            || !current
+                .monitor_channels
+                .iter()
+                .any(|v| *v == client.channel_id())
+            || client.client_type() == 1
         {
-            Ok(clients) => clients,
-            Err(_) => continue,
-        };
+            continue;
+        }
+        let key = channel_key(client.client_database_id(), server_id, client.channel_id());
 
-        'outer: for client in clients {
-            if client.client_database_id() == who_am_i.cldbid()
-                || !monitor_channels.iter().any(|v| *v == client.channel_id())
-                || client.client_type() == 1
-            {
-                continue;
-            }
-            let key = format!(
-                "ts_autochannel_{}_{server_id}_{pid}",
+        let ret = storage.get(&key).await?;
+        let create_new = ret.is_none();
+        let target_channel = if create_new {
+            conn.send_text_message(client.client_id(), &current.message.channel_not_found())
+                .await
+                .map_err(|e| error!("Got error while send message: {:?}", e))
+                .ok();
+
+            let channel_option = &current.channel_options[&client.channel_id()];
+            let parent = if NameTemplate::need_parent(channel_option.name_template()) {
+                conn.query_channels()
+                    .await
+                    .map_err(|e| error!("Got error while query channels: {:?}", e))
+                    .ok()
+                    .and_then(|channels| {
+                        channels
+                            .into_iter()
+                            .find(|channel| channel.cid() == client.channel_id())
+                    })
+                    .map(|channel| channel.channel_name().to_string())
+                    .unwrap_or_default()
+            } else {
+                String::new()
+            };
+            let template = NameTemplate::new(
+                channel_option.name_template(),
+                client.client_nickname(),
                 client.client_database_id(),
-                server_id = server_info.virtualserver_unique_identifier(),
-                pid = client.channel_id()
+                &parent,
             );
 
-            let ret: Option<i64> = redis_conn.get(&key).await?;
-            let create_new = ret.is_none();
-            let target_channel = if create_new {
-                conn.send_text_message(client.client_id(), MSG_CHANNEL_NOT_FOUND.get().unwrap())
+            let properties = channel_option.properties();
+            let mut counter = 1;
+            let channel_id = loop {
+                let name = template.render(counter);
+                let create_channel = match conn
+                    .create_channel(
+                        &name,
+                        client.channel_id(),
+                        channel_option.channel_type(),
+                        &properties,
+                    )
+                    .await
+                {
+                    Ok(ret) => ret,
+                    Err(e) => {
+                        if e.code() == 771 && counter < CHANNEL_NAME_RETRIE_TIMES {
+                            counter += 1;
+                            continue;
+                        }
+                        error!("Got error while create {:?} channel: {:?}", name, e);
+                        Record::new(
+                            Action::CreateChannel,
+                            server_id,
+                            client.channel_id(),
+                            e.code(),
+                        )
+                        .client(&client)
+                        .detail(name)
+                        .write();
+                        continue 'outer;
+                    }
+                };
+
+                conn.send_text_message(client.client_id(), &current.message.create_channel())
                     .await
                     .map_err(|e| error!("Got error while send message: {:?}", e))
                     .ok();
 
-                let mut name = format!("{}'s channel", client.client_nickname());
-                let channel_id = loop {
-                    let create_channel = match conn.create_channel(&name, client.channel_id()).await
-                    {
-                        Ok(ret) => ret,
-                        Err(e) => {
-                            if e.code() == 771 {
-                                name.push('1');
-                                continue;
-                            }
-                            error!("Got error while create {:?} channel: {:?}", name, e);
-                            continue 'outer;
-                        }
-                    };
+                let channel_id = create_channel.unwrap().cid();
+                METRICS.channel_created();
+                Record::new(Action::CreateChannel, server_id, channel_id, 0)
+                    .client(&client)
+                    .detail(name)
+                    .write();
+                break channel_id;
+            };
 
-                    conn.send_text_message(client.client_id(), MSG_CREATE_CHANNEL.get().unwrap())
-                        .await
-                        .map_err(|e| error!("Got error while send message: {:?}", e))
-                        .ok();
+            let ret = conn
+                .set_client_channel_group(
+                    client.client_database_id(),
+                    channel_id,
+                    current.privilege_group,
+                )
+                .await;
+            if let Err(e) = &ret {
+                error!("Got error while set client channel group: {:?}", e);
+            }
+            Record::new(
+                Action::GrantPermission,
+                server_id,
+                channel_id,
+                audit::code(&ret),
+            )
+            .client(&client)
+            .detail(format!("channel group {}", current.privilege_group))
+            .write();
 
-                    break create_channel.unwrap().cid();
-                };
+            if !current.default_permission.is_empty() {
+                let ret = conn
+                    .add_channel_permission(channel_id, &current.default_permission)
+                    .await;
+                if let Err(e) = &ret {
+                    error!("Got error while set default channel permissions: {:?}", e);
+                }
+                Record::new(
+                    Action::GrantPermission,
+                    server_id,
+                    channel_id,
+                    audit::code(&ret),
+                )
+                .client(&client)
+                .detail(format!("{:?}", current.default_permission))
+                .write();
+            }
 
-                conn.set_client_channel_group(
-                    client.client_database_id(),
+            if let Some(permissions) = current.channel_permissions.get(&client.channel_id()) {
+                let ret = conn.add_channel_permission(channel_id, permissions).await;
+                if let Err(e) = &ret {
+                    error!("Got error while set channel permissions: {:?}", e);
+                }
+                Record::new(
+                    Action::GrantPermission,
+                    server_id,
                     channel_id,
-                    privilege_group,
+                    audit::code(&ret),
                 )
-                .await
-                .map_err(|e| error!("Got error while set client channel group: {:?}", e))
-                .ok();
+                .client(&client)
+                .detail(format!("{:?}", permissions))
+                .write();
+            }
 
-                conn.add_channel_permission(channel_id, &[(133, 75)])
-                    .await
-                    .map_err(|e| error!("Got error while set default channel permissions: {:?}", e))
-                    .ok();
+            channel_id
+        } else {
+            ret.unwrap()
+        };
 
-                if let Some(permissions) = channel_permissions.get(&client.channel_id()) {
-                    conn.add_channel_permission(channel_id, permissions)
-                        .await
-                        .map_err(|e| error!("Got error while set channel permissions: {:?}", e))
-                        .ok();
+        let ret = conn
+            .move_client_to_channel(client.client_id(), target_channel)
+            .await;
+        Record::new(
+            Action::MoveClient,
+            server_id,
+            target_channel,
+            audit::code(&ret),
+        )
+        .client(&client)
+        .write();
+        match ret {
+            Ok(ret) => ret,
+            Err(e) => {
+                if e.code() == 768 {
+                    storage.del(&key).await?;
+                    skip_sleep = true;
+                    continue;
                 }
+                error!("Got error while move client: {:?}", e);
+                continue;
+            }
+        };
 
-                channel_id
-            } else {
-                ret.unwrap()
-            };
+        METRICS.client_moved();
+        if !create_new {
+            METRICS.channel_reused();
+        }
+
+        conn.send_text_message(client.client_id(), &current.message.move_to_channel())
+            .await
+            .map_err(|e| error!("Got error while send message: {:?}", e))
+            .ok();
 
-            match conn
-                .move_client_to_channel(client.client_id(), target_channel)
+        if create_new {
+            conn.move_client_to_channel(who_am_i.clid(), client.channel_id())
                 .await
-            {
-                Ok(ret) => ret,
-                Err(e) => {
-                    if e.code() == 768 {
-                        redis_conn.del(&key).await?;
-                        skip_sleep = true;
-                        continue;
+                .map_err(|e| error!("Unable move self out of channel. {:?}", e))
+                .ok();
+            //mapper.insert(client.client_database_id(), target_channel);
+            storage.set(&key, target_channel).await?;
+        }
+
+        info!("Move {} to {}", client.client_nickname(), target_channel);
+    }
+    Ok(skip_sleep)
+}
+
+async fn staff(
+    mut conn: Box<dyn QueryConn>,
+    config: Config,
+    server_id: i64,
+    mut storage: SharedStorage,
+    mut shutdown: tokio::sync::watch::Receiver<bool>,
+    mut config_receiver: tokio::sync::watch::Receiver<Config>,
+    mut admin_receiver: AdminReceiver,
+) -> anyhow::Result<()> {
+    let event_driven = config.misc().event_driven() && conn.support_notifies();
+    if config.misc().event_driven() && !event_driven {
+        warn!("Notifies are not supported by current connection, fallback to polling");
+    }
+    let mut cleaner = config.cleanup().as_ref().map(Cleaner::new);
+
+    let mut who_am_i = conn
+        .who_am_i()
+        .await
+        .map_err(|e| anyhow!("Whoami failed: {:?}", e))?;
+
+    let server_info = conn
+        .query_server_info()
+        .await
+        .map_err(|e| anyhow!("Query server info error: {:?}", e))?;
+
+    let server = config
+        .server_by_id(server_id)
+        .ok_or_else(|| anyhow!("Server {} not found in configure file", server_id))?;
+    let mut current = StaffConfig::load(conn.as_mut(), &config, server, event_driven).await?;
+
+    info!(
+        "Server {} interval is: {}, event driven: {}, version: {}",
+        server_id,
+        current.interval,
+        event_driven,
+        env!("CARGO_PKG_VERSION")
+    );
+
+    if event_driven {
+        conn.register_notifies()
+            .await
+            .map_err(|e| anyhow!("Register notifies failed: {:?}", e))?;
+    }
+
+    info!("Server {} connected: {}", server_id, who_am_i.clid());
+
+    // Wake up in time to report heartbeat, even if nothing happened
+    let heartbeat_interval = systemd::watchdog_timeout().map(|timeout| timeout / 4);
+
+    let mut skip_sleep = false;
+    let mut next_sweep = Instant::now();
+    loop {
+        systemd::heartbeat(server_id);
+        // Outside of select, so restore of session is never cancelled halfway
+        conn.ensure_connected().await;
+        if conn.take_reconnected() {
+            match conn.who_am_i().await {
+                Ok(ret) => who_am_i = ret,
+                Err(e) => error!("Whoami failed after reconnect: {:?}", e),
+            }
+        }
+
+        let mut idle = false;
+        if skip_sleep {
+            skip_sleep = false;
+        } else {
+            // In event driven mode, wait until someone enters a monitored channel,
+            // fallback to sweep if nothing happened
+            let deadline = cleaner
+                .as_ref()
+                .map_or(next_sweep, |cleaner| next_sweep.min(cleaner.next_run()));
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let keepalive_at = conn.last_active() + Duration::from_secs(current.keepalive_interval);
+            let triggered = tokio::select! {
+                _ = shutdown.changed() => {
+                    info!("Server {} exit!", server_id);
+                    break;
+                }
+                Ok(_) = config_receiver.changed() => {
+                    let new_config = config_receiver.borrow().clone();
+                    let server = match new_config.server_by_id(server_id) {
+                        Some(server) => server,
+                        None => {
+                            warn!(
+                                "Server {} is removed from configure file, restart is required",
+                                server_id
+                            );
+                            continue;
+                        }
+                    };
+                    let staff_config =
+                        StaffConfig::load(conn.as_mut(), &new_config, server, event_driven).await;
+                    match staff_config {
+                        Ok(staff_config) => {
+                            current = staff_config;
+                            cleaner = match (cleaner.take(), new_config.cleanup()) {
+                                (Some(mut cleaner), Some(cleanup)) => {
+                                    cleaner.update(cleanup);
+                                    Some(cleaner)
+                                }
+                                (_, cleanup) => cleanup.as_ref().map(Cleaner::new),
+                            };
+                            info!("Server {} new configure applied", server_id);
+                        }
+                        Err(e) => error!(
+                            "Server {} reject new configure, keep current: {:?}",
+                            server_id, e
+                        ),
                     }
-                    error!("Got error while move client: {:?}", e);
                     continue;
                 }
+                Some(message) = admin_receiver.recv() => {
+                    let ret = admin::handle(
+                        conn.as_mut(),
+                        &mut storage,
+                        server_info.virtualserver_unique_identifier(),
+                        current.privilege_group,
+                        &message.request,
+                    )
+                    .await;
+                    message.reply.send(ret).ok();
+                    // Resync means sweep right now
+                    message.request == admin::AdminRequest::Resync
+                }
+                _ = tokio::time::sleep_until(keepalive_at.into()), if current.keepalive_interval > 0 => {
+                    if let Err(e) = conn.keepalive().await {
+                        error!("Keepalive failed, ServerQuery session may be lost: {:?}", e);
+                    }
+                    continue;
+                }
+                ret = conn.wait_notifies(timeout), if event_driven => {
+                    match ret {
+                        Ok(notifies) => {
+                            for message in notifies
+                                .iter()
+                                .filter_map(|notify| notify.private_message())
+                                .filter(|message| message.invoker_id() != who_am_i.clid())
+                            {
+                                commands::handle(
+                                    conn.as_mut(),
+                                    &mut storage,
+                                    &current.monitor_channels,
+                                    server_info.virtualserver_unique_identifier(),
+                                    current.privilege_group,
+                                    message,
+                                )
+                                .await
+                                .map_err(|e| error!("Got error while handle command: {:?}", e))
+                                .ok();
+                            }
+                            notifies.iter().any(|notify| {
+                                notify
+                                    .entered(&current.monitor_channels)
+                                    .iter()
+                                    .any(|view| view.client_id() != who_am_i.clid())
+                            })
+                        }
+                        Err(e) => {
+                            error!("Got error while wait notifies: {:?}", e);
+                            false
+                        }
+                    }
+                }
+                _ = tokio::time::sleep(timeout), if !event_driven => false,
+                _ = tokio::time::sleep(heartbeat_interval.unwrap_or_default()),
+                    if heartbeat_interval.is_some() => continue,
             };
+            idle = !triggered && Instant::now() < next_sweep;
+        }
 
-            conn.send_text_message(client.client_id(), MSG_MOVE_TO_CHANNEL.get().unwrap())
+        if let Some(cleaner) = cleaner.as_mut().filter(|cleaner| cleaner.due()) {
+            cleaner
+                .sweep(
+                    conn.as_mut(),
+                    &mut storage,
+                    server_info.virtualserver_unique_identifier(),
+                    &current.channel_options,
+                )
                 .await
-                .map_err(|e| error!("Got error while send message: {:?}", e))
+                .map_err(|e| error!("Got error while cleanup channels: {:?}", e))
                 .ok();
+        }
 
-            if create_new {
-                conn.move_client_to_channel(who_am_i.clid(), client.channel_id())
-                    .await
-                    .map_err(|e| anyhow!("Unable move self out of channel. {:?}", e))?;
-                //mapper.insert(client.client_database_id(), target_channel);
-                redis_conn.set(&key, target_channel).await?;
-            }
-
-            info!("Move {} to {}", client.client_nickname(), target_channel);
+        if idle {
+            continue;
         }
+        next_sweep = Instant::now() + Duration::from_millis(current.interval);
+        skip_sleep = sweep_clients(
+            conn.as_mut(),
+            &mut storage,
+            &current,
+            &who_am_i,
+            server_info.virtualserver_unique_identifier(),
+        )
+        .await?;
     }
     conn.logout().await?;
     Ok(())
@@ -261,49 +729,323 @@ async fn staff(
 async fn configure_file_bootstrap<P: AsRef<Path>>(
     path: P,
     systemd_mode: bool,
+    dry_run: bool,
 ) -> anyhow::Result<()> {
     let config = Config::try_from(path.as_ref())?;
-    MSG_CHANNEL_NOT_FOUND
-        .set(config.message().channel_not_found())
-        .unwrap();
-    MSG_CREATE_CHANNEL
-        .set(config.message().create_channel())
-        .unwrap();
-    MSG_MOVE_TO_CHANNEL
-        .set(config.message().move_to_channel())
-        .unwrap();
     SYSTEMD_MODE
         .set(config.misc().systemd() || systemd_mode)
         .unwrap();
-    observer(
-        try_init_connection(&config, config.server().server_id()).await?,
-        config.server().channels(),
-        config.server().privilege_group_id(),
-        config.server().redis_server(),
-        config.misc().interval(),
-        config.channel_permissions(),
-    )
-    .await
+    // Nothing is really done in dry run mode, keep audit log clean
+    if dry_run {
+        warn!("Dry run mode, changes are logged but not applied");
+    } else if let Some(audit) = config.audit() {
+        audit::open(&audit.path())?;
+    }
+    observer(config, path.as_ref().to_path_buf(), dry_run).await
 }
 
 fn main() -> anyhow::Result<()> {
+    let server_arg =
+        || arg!(--server <SERVER_ID> "Virtual server id, default is all servers").required(false);
     let matches = Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .args(&[
             arg!([CONFIG_FILE] "Override default configure file location"),
-            arg!(--systemd "Start in systemd mode, which enable wait if connect failed"),
+            arg!(-c --config <CONFIG_FILE> "Same as CONFIG_FILE, can be given after subcommand")
+                .required(false)
+                .global(true),
+            arg!(--systemd "Start in systemd mode, which enable wait if connect failed")
+                .global(true),
+            arg!(--"dry-run" "Log changes which would be made instead of applying them")
+                .global(true),
         ])
+        .subcommand(Command::new("run").about("Start serving (default)"))
+        .subcommand(
+            Command::new("check-config")
+                .about("Check configure file against live server")
+                .arg(server_arg()),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Print stored mappings of channel owner to channel")
+                .arg(server_arg()),
+        )
+        .subcommand(
+            Command::new("purge")
+                .about("Remove mappings whose channel no longer exists")
+                .arg(server_arg()),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Copy stored mappings to another storage backend")
+                .args(&[
+                    arg!(<STORAGE> "Destination storage")
+                        .possible_values(["redis", "sqlite", "json"]),
+                    arg!(--"redis-server" <REDIS_SERVER> "Destination Redis server, default is redis_server in configure file")
+                        .required(false),
+                    arg!(--"storage-path" <PATH> "Destination database/file path, default is autochannel.db (sqlite) or autochannel.json (json)")
+                        .required(false),
+                    server_arg(),
+                ]),
+        )
+        .subcommand(
+            Command::new("transfer")
+                .about("Transfer ownership of channel to another client")
+                .args(&[
+                    arg!(<CHANNEL_ID> "Channel id to transfer"),
+                    arg!(<TARGET> "Nickname (online only) or unique id of new owner"),
+                    arg!(--server <SERVER_ID> "Virtual server id, default is first server")
+                        .required(false),
+                ]),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Print records of audit log, as JSON lines")
+                .args(&[
+                    arg!(--client <CLIENT> "Unique id, database id or nickname of client")
+                        .required(false),
+                    arg!(--channel <CHANNEL_ID> "Channel id").required(false),
+                    arg!(--action <ACTION> "Kind of action")
+                        .required(false)
+                        .possible_values([
+                            "create_channel",
+                            "grant_permission",
+                            "move_client",
+                            "delete_channel",
+                            "transfer",
+                            "command",
+                        ]),
+                    arg!(--since <TIMESTAMP> "Only records after this unix timestamp")
+                        .required(false),
+                    arg!(--limit <LIMIT> "Print latest records only")
+                        .required(false)
+                        .default_value("100"),
+                ]),
+        )
         .get_matches();
 
     env_logger::Builder::from_default_env().init();
-    tokio::runtime::Builder::new_multi_thread()
+    let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
-        .unwrap()
-        .block_on(configure_file_bootstrap(
-            matches.value_of("CONFIG_FILE").unwrap_or("config.toml"),
+        .unwrap();
+    let path = matches
+        .value_of("config")
+        .or_else(|| matches.value_of("CONFIG_FILE"))
+        .unwrap_or("config.toml");
+    let server_id = |sub_matches: &clap::ArgMatches| {
+        sub_matches
+            .value_of("server")
+            .map(|id| id.parse::<i64>())
+            .transpose()
+            .map_err(|e| anyhow!("Invalid server id: {:?}", e))
+    };
+
+    match matches.subcommand() {
+        Some(("check-config", sub_matches)) => {
+            runtime.block_on(cli::check_config(path, server_id(sub_matches)?))?
+        }
+        Some(("list", sub_matches)) => {
+            runtime.block_on(cli::list(path, server_id(sub_matches)?))?
+        }
+        Some(("purge", sub_matches)) => runtime.block_on(cli::purge(
+            path,
+            server_id(sub_matches)?,
+            sub_matches.is_present("dry-run"),
+        ))?,
+        Some(("migrate", sub_matches)) => runtime.block_on(cli::migrate(
+            path,
+            server_id(sub_matches)?,
+            sub_matches.value_of("STORAGE").unwrap().parse()?,
+            sub_matches.value_of("redis-server"),
+            sub_matches.value_of("storage-path").map(Path::new),
+            sub_matches.is_present("dry-run"),
+        ))?,
+        Some(("transfer", sub_matches)) => {
+            let cid = sub_matches
+                .value_of("CHANNEL_ID")
+                .unwrap()
+                .parse()
+                .map_err(|e| anyhow!("Invalid channel id: {:?}", e))?;
+            runtime.block_on(cli::transfer(
+                path,
+                server_id(sub_matches)?,
+                cid,
+                sub_matches.value_of("TARGET").unwrap(),
+                sub_matches.is_present("dry-run"),
+            ))?;
+        }
+        Some(("audit", sub_matches)) => {
+            let filter = audit::Filter {
+                client: sub_matches.value_of("client").map(|s| s.to_string()),
+                channel_id: sub_matches
+                    .value_of("channel")
+                    .map(|cid| cid.parse())
+                    .transpose()
+                    .map_err(|e| anyhow!("Invalid channel id: {:?}", e))?,
+                action: sub_matches
+                    .value_of("action")
+                    .map(|action| action.parse())
+                    .transpose()?,
+                since: sub_matches
+                    .value_of("since")
+                    .map(|since| since.parse())
+                    .transpose()
+                    .map_err(|e| anyhow!("Invalid timestamp: {:?}", e))?,
+            };
+            let limit = sub_matches
+                .value_of("limit")
+                .unwrap()
+                .parse()
+                .map_err(|e| anyhow!("Invalid limit: {:?}", e))?;
+            cli::audit(path, &filter, limit)?;
+        }
+        // `run` or no subcommand
+        Some((_, sub_matches)) => runtime.block_on(configure_file_bootstrap(
+            path,
+            sub_matches.is_present("systemd"),
+            sub_matches.is_present("dry-run"),
+        ))?,
+        None => runtime.block_on(configure_file_bootstrap(
+            path,
             matches.is_present("systemd"),
-        ))?;
+            matches.is_present("dry-run"),
+        ))?,
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use crate::cleanup::Cleaner;
+    use crate::datastructures::Config;
+    use crate::mock::MockServer;
+    use crate::query::QueryConn;
+    use crate::socketlib::SocketConn;
+    use crate::storage::{channel_key, MemoryStorage, Storage};
+    use crate::{sweep_clients, StaffConfig};
+    use std::time::Duration;
+
+    const TEST_CONFIG: &str = r#"
+[server]
+channel_id = [1]
+privilege_group_id = 5
+
+[misc]
+
+[cleanup]
+grace_period = 0
+
+[raw_query]
+user = "serveradmin"
+password = "114514"
+"#;
+
+    fn command_names(commands: &[String]) -> Vec<&str> {
+        commands
+            .iter()
+            .map(|command| command.split_whitespace().next().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test() {
+        let mock = MockServer::start().await;
+        let config: Config = toml::from_str(TEST_CONFIG).unwrap();
+        let mut conn = SocketConn::connect(&mock.endpoint()).await.unwrap();
+        let current = StaffConfig::load(&mut conn, &config, config.server(), false)
+            .await
+            .unwrap();
+        let who_am_i = conn.who_am_i().await.unwrap();
+        let mut storage = MemoryStorage::default();
+        let key = channel_key(10, "uid", 1);
+        mock.set_reply(
+            "clientlist",
+            "clid=1 cid=1 client_database_id=1 client_nickname=serveradmin client_type=1|clid=5 cid=1 client_database_id=10 client_nickname=foo client_type=0",
+        );
+
+        // Create, name is in use at first time
+        mock.push_error("channelcreate", 771, "channel name is already in use");
+        mock.push_reply("channelcreate", "cid=20");
+        mock.push_notify("notifycliententerview cfid=0 ctid=1 reasonid=0 clid=5");
+        mock.take_commands();
+        assert!(
+            !sweep_clients(&mut conn, &mut storage, &current, &who_am_i, "uid")
+                .await
+                .unwrap()
+        );
+        assert_eq!(storage.get(&key).await.unwrap(), Some(20));
+        let commands = mock.take_commands();
+        assert_eq!(
+            command_names(&commands),
+            vec![
+                "clientlist",
+                "sendtextmessage",
+                "channelcreate",
+                "channelcreate",
+                "sendtextmessage",
+                "setclientchannelgroup",
+                "channeladdperm",
+                "clientmove",
+                "sendtextmessage",
+                "clientmove"
+            ]
+        );
+        assert!(commands[3].contains("channel_name=foo's\\schannel\\s(2)"));
+        assert!(commands.contains(&"setclientchannelgroup cgid=5 cid=20 cldbid=10".to_string()));
+        assert!(commands.contains(&"channeladdperm cid=20 permid=133 permvalue=75".to_string()));
+        assert!(commands.contains(&"clientmove clid=5 cid=20".to_string()));
+        assert!(commands.contains(&"clientmove clid=1 cid=1".to_string()));
+
+        // Notify interleaved with replies is kept
+        let notifies = conn.wait_notifies(Duration::from_millis(10)).await.unwrap();
+        assert_eq!(notifies.len(), 1);
+        assert_eq!(notifies[0].entered(&[1]).len(), 1);
+
+        // Reuse
+        assert!(
+            !sweep_clients(&mut conn, &mut storage, &current, &who_am_i, "uid")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            mock.take_commands(),
+            vec![
+                "clientlist -uid".to_string(),
+                "clientmove clid=5 cid=20".to_string(),
+                "sendtextmessage targetmode=1 target=5 msg=You\\shave\\sbeen\\smoved\\sinto\\syour\\schannel.".to_string()
+            ]
+        );
+
+        // Channel is deleted, key should be removed and sweep again immediately
+        mock.push_error("clientmove", 768, "invalid channelID");
+        assert!(
+            sweep_clients(&mut conn, &mut storage, &current, &who_am_i, "uid")
+                .await
+                .unwrap()
+        );
+        assert_eq!(storage.get(&key).await.unwrap(), None);
+
+        mock.push_reply("channelcreate", "cid=21");
+        sweep_clients(&mut conn, &mut storage, &current, &who_am_i, "uid")
+            .await
+            .unwrap();
+        assert_eq!(storage.get(&key).await.unwrap(), Some(21));
+
+        // Channel is empty, delete it
+        mock.set_reply(
+            "channellist",
+            "cid=1 pid=0 channel_order=0 channel_name=Lobby total_clients=0 channel_needed_subscribe_power=0|cid=21 pid=1 channel_order=0 channel_name=foo total_clients=0 channel_needed_subscribe_power=0",
+        );
+        mock.take_commands();
+        Cleaner::new(config.cleanup().as_ref().unwrap())
+            .sweep(&mut conn, &mut storage, "uid", &current.channel_options)
+            .await
+            .unwrap();
+        assert!(mock
+            .take_commands()
+            .contains(&"channeldelete cid=21 force=0".to_string()));
+        assert_eq!(storage.get(&key).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,327 @@
+use crate::httpd;
+use crate::storage::Storage;
+use async_trait::async_trait;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const PREFIX: &str = "ts_autochannel";
+// Seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, upper) in self.buckets.iter_mut().zip(BUCKETS) {
+            if value <= upper {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Process wide counters, exported in Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    channels_created: AtomicU64,
+    channels_reused: AtomicU64,
+    client_moves: AtomicU64,
+    reconnects: AtomicU64,
+    storage_errors: AtomicU64,
+    query_errors: Mutex<BTreeMap<i32, u64>>,
+    // Keyed by server unique identifier
+    monitored_clients: Mutex<BTreeMap<String, usize>>,
+    auto_channels: Mutex<BTreeMap<String, usize>>,
+    // Keyed by ServerQuery command
+    query_duration: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl Metrics {
+    pub fn channel_created(&self) {
+        self.channels_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn channel_reused(&self) {
+        self.channels_reused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_moved(&self) {
+        self.client_moves.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn storage_error(&self) {
+        self.storage_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn query_error(&self, code: i32) {
+        *self.query_errors.lock().unwrap().entry(code).or_default() += 1;
+    }
+
+    pub fn set_monitored_clients(&self, server_id: &str, count: usize) {
+        self.monitored_clients
+            .lock()
+            .unwrap()
+            .insert(server_id.to_string(), count);
+    }
+
+    pub fn set_auto_channels(&self, server_id: &str, count: usize) {
+        self.auto_channels
+            .lock()
+            .unwrap()
+            .insert(server_id.to_string(), count);
+    }
+
+    pub fn observe_query(&self, command: &str, duration: Duration) {
+        self.query_duration
+            .lock()
+            .unwrap()
+            .entry(command.to_string())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    /// One line summary of counters, used as systemd status.
+    pub fn summary(&self) -> String {
+        format!(
+            "Monitoring {} client(s), {} auto-channel(s), created {} channel(s), moved {} client(s), reconnected {} time(s)",
+            self.monitored_clients.lock().unwrap().values().sum::<usize>(),
+            self.auto_channels.lock().unwrap().values().sum::<usize>(),
+            self.channels_created.load(Ordering::Relaxed),
+            self.client_moves.load(Ordering::Relaxed),
+            self.reconnects.load(Ordering::Relaxed),
+        )
+    }
+
+    fn write_header(s: &mut String, name: &str, kind: &str, help: &str) {
+        writeln!(s, "# HELP {}_{} {}", PREFIX, name, help).unwrap();
+        writeln!(s, "# TYPE {}_{} {}", PREFIX, name, kind).unwrap();
+    }
+
+    fn write_counter(s: &mut String, name: &str, help: &str, value: &AtomicU64) {
+        Self::write_header(s, name, "counter", help);
+        writeln!(s, "{}_{} {}", PREFIX, name, value.load(Ordering::Relaxed)).unwrap();
+    }
+
+    fn write_gauge(
+        s: &mut String,
+        name: &str,
+        help: &str,
+        values: &Mutex<BTreeMap<String, usize>>,
+    ) {
+        Self::write_header(s, name, "gauge", help);
+        for (server_id, value) in values.lock().unwrap().iter() {
+            writeln!(
+                s,
+                "{}_{}{{server=\"{}\"}} {}",
+                PREFIX, name, server_id, value
+            )
+            .unwrap();
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+        Self::write_counter(
+            &mut s,
+            "channels_created_total",
+            "Channels created for clients",
+            &self.channels_created,
+        );
+        Self::write_counter(
+            &mut s,
+            "channels_reused_total",
+            "Clients moved to their existing channel",
+            &self.channels_reused,
+        );
+        Self::write_counter(
+            &mut s,
+            "client_moves_total",
+            "Clients moved to their channel",
+            &self.client_moves,
+        );
+        Self::write_counter(
+            &mut s,
+            "reconnects_total",
+            "Reconnects of ServerQuery connection",
+            &self.reconnects,
+        );
+        Self::write_counter(
+            &mut s,
+            "storage_errors_total",
+            "Errors returned by storage backend",
+            &self.storage_errors,
+        );
+
+        Self::write_header(
+            &mut s,
+            "query_errors_total",
+            "counter",
+            "Errors returned by server, by error code",
+        );
+        for (code, value) in self.query_errors.lock().unwrap().iter() {
+            writeln!(
+                s,
+                "{}_query_errors_total{{code=\"{}\"}} {}",
+                PREFIX, code, value
+            )
+            .unwrap();
+        }
+
+        Self::write_gauge(
+            &mut s,
+            "monitored_clients",
+            "Clients in monitored channels at last sweep",
+            &self.monitored_clients,
+        );
+        Self::write_gauge(
+            &mut s,
+            "auto_channels",
+            "Auto-created channels which still exist at last cleanup",
+            &self.auto_channels,
+        );
+
+        Self::write_header(
+            &mut s,
+            "query_duration_seconds",
+            "histogram",
+            "Latency of ServerQuery commands",
+        );
+        for (command, histogram) in self.query_duration.lock().unwrap().iter() {
+            let name = format!("{}_query_duration_seconds", PREFIX);
+            for (upper, count) in BUCKETS.iter().zip(histogram.buckets) {
+                writeln!(
+                    s,
+                    "{}_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                    name, command, upper, count
+                )
+                .unwrap();
+            }
+            writeln!(
+                s,
+                "{}_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+                name, command, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                s,
+                "{}_sum{{command=\"{}\"}} {}",
+                name, command, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                s,
+                "{}_count{{command=\"{}\"}} {}",
+                name, command, histogram.count
+            )
+            .unwrap();
+        }
+        s
+    }
+}
+
+/// Count errors of wrapped storage.
+pub struct MeteredStorage(Box<dyn Storage>);
+
+impl MeteredStorage {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        Self(storage)
+    }
+}
+
+fn metered<T>(ret: anyhow::Result<T>) -> anyhow::Result<T> {
+    if ret.is_err() {
+        METRICS.storage_error();
+    }
+    ret
+}
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>> {
+        metered(self.0.get(key).await)
+    }
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()> {
+        metered(self.0.set(key, cid).await)
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        metered(self.0.del(key).await)
+    }
+
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool> {
+        metered(self.0.transfer(from, to).await)
+    }
+
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>> {
+        metered(self.0.keys(server_id).await)
+    }
+}
+
+async fn route(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    Ok(match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("content-type", "text/plain; version=0.0.4")
+            .body(Body::from(METRICS.render()))
+            .unwrap(),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    })
+}
+
+pub fn bind(listen: &str) -> anyhow::Result<impl Future<Output = anyhow::Result<()>>> {
+    httpd::bind("Metrics", listen, route)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::metrics::Metrics;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let metrics = Metrics::default();
+        metrics.channel_created();
+        metrics.query_error(771);
+        metrics.query_error(771);
+        metrics.set_monitored_clients("uid", 3);
+        metrics.observe_query("clientlist", Duration::from_millis(20));
+        let s = metrics.render();
+        assert!(s.contains("ts_autochannel_channels_created_total 1\n"));
+        assert!(s.contains("ts_autochannel_query_errors_total{code=\"771\"} 2\n"));
+        assert!(s.contains("ts_autochannel_monitored_clients{server=\"uid\"} 3\n"));
+        assert!(s.contains(
+            "ts_autochannel_query_duration_seconds_bucket{command=\"clientlist\",le=\"0.01\"} 0\n"
+        ));
+        assert!(s.contains(
+            "ts_autochannel_query_duration_seconds_bucket{command=\"clientlist\",le=\"0.025\"} 1\n"
+        ));
+        assert!(
+            s.contains("ts_autochannel_query_duration_seconds_count{command=\"clientlist\"} 1\n")
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::transport::Endpoint;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const BANNER: &str = "TS3\n\rWelcome to the TeamSpeak 3 ServerQuery interface, type \"help\" for a list of commands and \"help <command>\" for information on a specific command.\n\r";
+
+#[derive(Debug, Default)]
+struct MockState {
+    // One-shot replies, take precedence over default replies
+    queued: HashMap<String, VecDeque<String>>,
+    defaults: HashMap<String, String>,
+    notifies: VecDeque<String>,
+    commands: Vec<String>,
+}
+
+impl MockState {
+    // None if connection should be closed
+    fn reply(&mut self, line: &str) -> Option<String> {
+        self.commands.push(line.to_string());
+        let command = line.split_whitespace().next().unwrap_or_default();
+        let reply = self
+            .queued
+            .get_mut(command)
+            .and_then(|replies| replies.pop_front())
+            .or_else(|| self.defaults.get(command).cloned())
+            .unwrap_or_else(|| MockServer::ok(""));
+        if reply.is_empty() {
+            return None;
+        }
+        Some(self.notifies.drain(..).collect::<String>() + &reply)
+    }
+}
+
+/// In-process fake ServerQuery server, replies are scripted per command.
+///
+/// Command without scripted reply gets `error id=0 msg=ok`,
+/// queued notifies are sent right before next reply.
+#[derive(Clone)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let self_ = Self {
+            addr: listener.local_addr().unwrap(),
+            state: Default::default(),
+        };
+        self_.set_reply("whoami", "client_id=1 client_database_id=1");
+        self_.set_reply(
+            "serverinfo",
+            "virtualserver_unique_identifier=uid virtualserver_default_channel_group=8",
+        );
+        self_.set_reply("version", "version=3.13.7 build=1655727713 platform=Linux");
+
+        let state = self_.state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(Self::serve(stream, state.clone()));
+            }
+        });
+        self_
+    }
+
+    async fn serve(mut stream: TcpStream, state: Arc<Mutex<MockState>>) {
+        if stream.write_all(BANNER.as_bytes()).await.is_err() {
+            return;
+        }
+        let mut buffer = Vec::new();
+        let mut data = [0u8; 1024];
+        loop {
+            let size = match stream.read(&mut data).await {
+                Ok(0) | Err(_) => return,
+                Ok(size) => size,
+            };
+            buffer.extend_from_slice(&data[..size]);
+            while let Some(pos) = buffer.windows(2).position(|w| w == b"\n\r") {
+                let line = String::from_utf8_lossy(&buffer[..pos]).to_string();
+                buffer.drain(..pos + 2);
+                let reply = match state.lock().unwrap().reply(&line) {
+                    Some(reply) => reply,
+                    None => return,
+                };
+                if stream.write_all(reply.as_bytes()).await.is_err() {
+                    return;
+                }
+                if line == "quit" {
+                    return;
+                }
+            }
+        }
+    }
+
+    pub fn ok(data: &str) -> String {
+        if data.is_empty() {
+            "error id=0 msg=ok\n\r".to_string()
+        } else {
+            format!("{}\n\rerror id=0 msg=ok\n\r", data)
+        }
+    }
+
+    pub fn error(id: i32, msg: &str) -> String {
+        format!("error id={} msg={}\n\r", id, msg.replace(' ', "\\s"))
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint::Tcp {
+            server: self.addr.ip().to_string(),
+            port: self.addr.port(),
+        }
+    }
+
+    /// Reply `data` to every `command` unless one-shot reply is queued.
+    pub fn set_reply(&self, command: &str, data: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .defaults
+            .insert(command.to_string(), Self::ok(data));
+    }
+
+    /// Reply `data` to next `command` only.
+    pub fn push_reply(&self, command: &str, data: &str) {
+        self.push_raw(command, Self::ok(data));
+    }
+
+    /// Reply error to next `command` only.
+    pub fn push_error(&self, command: &str, id: i32, msg: &str) {
+        self.push_raw(command, Self::error(id, msg));
+    }
+
+    /// Close connection instead of reply to next `command`.
+    pub fn push_disconnect(&self, command: &str) {
+        self.push_raw(command, String::new());
+    }
+
+    fn push_raw(&self, command: &str, reply: String) {
+        self.state
+            .lock()
+            .unwrap()
+            .queued
+            .entry(command.to_string())
+            .or_default()
+            .push_back(reply);
+    }
+
+    pub fn push_notify(&self, line: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .notifies
+            .push_back(format!("{}\n\r", line));
+    }
+
+    /// Take commands received since last call.
+    pub fn take_commands(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.lock().unwrap().commands)
+    }
+}
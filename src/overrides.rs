@@ -0,0 +1,211 @@
+use anyhow::anyhow;
+use std::fs::read_to_string;
+use toml::Value;
+
+pub const ENV_PREFIX: &str = "TS_AUTOCHANNEL_";
+const FILE_SUFFIX: &str = "_file";
+// Credentials are always strings, even if they look like numbers or booleans
+const STRING_KEYS: [&str; 5] = ["user", "password", "api_key", "token", "redis_server"];
+
+pub fn has_env() -> bool {
+    std::env::vars().any(|(key, _)| key.starts_with(ENV_PREFIX))
+}
+
+/// Override `value` by variables like `TS_AUTOCHANNEL_RAW_QUERY__PASSWORD`,
+/// `__` separates keys of nested tables (or index of array). Return true if anything changed.
+pub fn apply_env<I: IntoIterator<Item = (String, String)>>(
+    value: &mut Value,
+    vars: I,
+) -> anyhow::Result<bool> {
+    let mut vars = vars
+        .into_iter()
+        .filter_map(|(key, raw)| {
+            key.strip_prefix(ENV_PREFIX)
+                .map(|key| (key.to_lowercase(), raw))
+        })
+        .collect::<Vec<_>>();
+    vars.sort_unstable();
+    for (key, raw) in &vars {
+        set(value, key, raw).map_err(|e| {
+            anyhow!(
+                "Apply environment variable {}{} error: {}",
+                ENV_PREFIX,
+                key.to_uppercase(),
+                e
+            )
+        })?;
+    }
+    Ok(!vars.is_empty())
+}
+
+// Parse as TOML value, but keep type of existing string (quoted value is always string)
+fn parse_value(raw: &str, current: Option<&Value>) -> Value {
+    let parsed = toml::from_str::<toml::value::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("v"));
+    match (current, parsed) {
+        (_, Some(Value::String(s))) => Value::String(s),
+        (Some(Value::String(_)), _) | (_, None) => Value::String(raw.to_string()),
+        (_, Some(value)) => value,
+    }
+}
+
+fn set(value: &mut Value, key: &str, raw: &str) -> anyhow::Result<()> {
+    let mut current = value;
+    let mut segments = key.split("__").peekable();
+    while let Some(segment) = segments.next() {
+        if segment.is_empty() {
+            return Err(anyhow!("Empty key"));
+        }
+        let last = segments.peek().is_none();
+        current = match current {
+            Value::Table(table) => {
+                if last {
+                    // Later layer wins, whether value is given directly or by file
+                    match segment.strip_suffix(FILE_SUFFIX) {
+                        Some(target) => table.remove(target),
+                        None => table.remove(&format!("{}{}", segment, FILE_SUFFIX)),
+                    };
+                    let value = if STRING_KEYS.contains(&segment) || segment.ends_with(FILE_SUFFIX)
+                    {
+                        Value::String(raw.to_string())
+                    } else {
+                        parse_value(raw, table.get(segment))
+                    };
+                    table.insert(segment.to_string(), value);
+                    return Ok(());
+                }
+                table
+                    .entry(segment.to_string())
+                    .or_insert_with(|| Value::Table(Default::default()))
+            }
+            Value::Array(array) => {
+                let index = segment
+                    .parse::<usize>()
+                    .map_err(|_| anyhow!("{} is not an array index", segment))?;
+                let len = array.len();
+                let item = array
+                    .get_mut(index)
+                    .ok_or_else(|| anyhow!("Index {} out of range (length {})", index, len))?;
+                if last {
+                    *item = parse_value(raw, Some(item));
+                    return Ok(());
+                }
+                item
+            }
+            _ => return Err(anyhow!("Parent of {} is not a table", segment)),
+        };
+    }
+    Ok(())
+}
+
+/// Replace `<key>_file = "path"` by `<key> = "<content of path>"` recursively,
+/// trailing newline is removed. Return true if anything changed.
+pub fn resolve_files(value: &mut Value) -> anyhow::Result<bool> {
+    let mut changed = false;
+    match value {
+        Value::Table(table) => {
+            let keys = table
+                .iter()
+                .filter(|(key, value)| key.ends_with(FILE_SUFFIX) && value.is_str())
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            for key in keys {
+                let target = key.strip_suffix(FILE_SUFFIX).unwrap().to_string();
+                if table.contains_key(&target) {
+                    return Err(anyhow!("Only one of {} and {} can be set", target, key));
+                }
+                let path = table.remove(&key).unwrap();
+                let path = path.as_str().unwrap();
+                let content = read_to_string(path)
+                    .map_err(|e| anyhow!("Read {} ({}) error: {:?}", key, path, e))?;
+                table.insert(
+                    target,
+                    Value::String(content.trim_end_matches(['\r', '\n']).to_string()),
+                );
+                changed = true;
+            }
+            for (_, value) in table.iter_mut() {
+                changed |= resolve_files(value)?;
+            }
+        }
+        Value::Array(array) => {
+            for value in array {
+                changed |= resolve_files(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::overrides::{apply_env, resolve_files};
+    use toml::Value;
+
+    #[test]
+    fn test() {
+        let mut value: Value = toml::from_str(
+            r#"
+[[server]]
+channel_id = [1]
+privilege_group_id = 5
+
+[[server]]
+server_id = 2
+channel_id = [3]
+privilege_group_id = 5
+
+[raw_query]
+user = "serveradmin"
+password = "114514"
+"#,
+        )
+        .unwrap();
+
+        let path = std::env::temp_dir().join(format!("ts-secret-{}", std::process::id()));
+        std::fs::write(&path, "redis://:secret@127.0.0.1\n").unwrap();
+        let vars = [
+            ("TS_AUTOCHANNEL_RAW_QUERY__PASSWORD", "1919810"),
+            ("TS_AUTOCHANNEL_RAW_QUERY__PORT", "10022"),
+            ("TS_AUTOCHANNEL_SERVER__1__PRIVILEGE_GROUP_ID", "6"),
+            (
+                "TS_AUTOCHANNEL_SERVER__0__REDIS_SERVER_FILE",
+                path.to_str().unwrap(),
+            ),
+            ("TS_AUTOCHANNEL_MISC__EVENT_DRIVEN", "false"),
+            ("TS_AUTOCHANNEL_WEB_QUERY__API_KEY", "114514"),
+            ("TS_AUTOCHANNEL_ADMIN__TOKEN", "true"),
+            ("OTHER_VARIABLE", "1"),
+        ]
+        .map(|(key, value)| (key.to_string(), value.to_string()));
+        assert!(apply_env(&mut value, vars).unwrap());
+        assert!(resolve_files(&mut value).unwrap());
+
+        assert_eq!(value["raw_query"]["password"].as_str(), Some("1919810"));
+        assert_eq!(value["raw_query"]["port"].as_integer(), Some(10022));
+        assert_eq!(
+            value["server"][1]["privilege_group_id"].as_integer(),
+            Some(6)
+        );
+        assert_eq!(
+            value["server"][0]["redis_server"].as_str(),
+            Some("redis://:secret@127.0.0.1")
+        );
+        assert_eq!(value["misc"]["event_driven"].as_bool(), Some(false));
+        assert_eq!(value["web_query"]["api_key"].as_str(), Some("114514"));
+        assert_eq!(value["admin"]["token"].as_str(), Some("true"));
+
+        let vars = [("TS_AUTOCHANNEL_SERVER__2__SERVER_ID", "3")]
+            .map(|(key, value)| (key.to_string(), value.to_string()));
+        assert!(apply_env(&mut value, vars).is_err());
+
+        value["raw_query"]
+            .as_table_mut()
+            .unwrap()
+            .insert("password_file".to_string(), Value::String("x".to_string()));
+        assert!(resolve_files(&mut value).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+use crate::datastructures::config::{ChannelProperties, ChannelType};
+use crate::datastructures::{
+    Channel, ChannelGroup, Client, ClientDatabaseId, CreateChannel, Notifies, PermissionInfo,
+    QueryResult, ServerInfo, WhoAmI,
+};
+use async_trait::async_trait;
+use std::time::{Duration, Instant};
+
+/// Operations shared by ServerQuery (raw/ssh) and WebQuery backends.
+#[async_trait]
+pub trait QueryConn: Send {
+    /// Whether backend can push notifies, polling should be used if not.
+    fn support_notifies(&self) -> bool {
+        true
+    }
+
+    /// Re-establish lost connection (and session), wait until succeed.
+    async fn ensure_connected(&mut self) {}
+
+    async fn wait_notifies(&mut self, timeout: Duration) -> anyhow::Result<Vec<Notifies>>;
+
+    /// Return true once if connection was re-established since last call,
+    /// client id of current session is changed in this case.
+    fn take_reconnected(&mut self) -> bool;
+
+    async fn register_notifies(&mut self) -> QueryResult<()>;
+
+    fn last_active(&self) -> Instant;
+
+    async fn keepalive(&mut self) -> QueryResult<()>;
+
+    async fn who_am_i(&mut self) -> QueryResult<WhoAmI>;
+
+    async fn send_text_message(&mut self, clid: i64, text: &str) -> QueryResult<()>;
+
+    async fn query_server_info(&mut self) -> QueryResult<ServerInfo>;
+
+    async fn query_channels(&mut self) -> QueryResult<Vec<Channel>>;
+
+    /// Channel with clients inside is deleted only if `force` is true.
+    async fn delete_channel(&mut self, cid: i64, force: bool) -> QueryResult<()>;
+
+    async fn create_channel(
+        &mut self,
+        name: &str,
+        pid: i64,
+        channel_type: Option<ChannelType>,
+        properties: &ChannelProperties,
+    ) -> QueryResult<Option<CreateChannel>>;
+
+    async fn edit_channel(&mut self, cid: i64, properties: &[(&str, String)]) -> QueryResult<()>;
+
+    async fn query_clients(&mut self) -> QueryResult<Vec<Client>>;
+
+    async fn query_client_database_id(&mut self, uid: &str) -> QueryResult<ClientDatabaseId>;
+
+    /// Kick client from its channel to server default channel.
+    async fn kick_client_from_channel(&mut self, clid: i64, reason: &str) -> QueryResult<()>;
+
+    async fn move_client_to_channel(&mut self, clid: i64, target_channel: i64) -> QueryResult<()>;
+
+    async fn set_client_channel_group(
+        &mut self,
+        client_database_id: i64,
+        channel_id: i64,
+        group_id: i64,
+    ) -> QueryResult<()>;
+
+    async fn add_channel_permission(
+        &mut self,
+        target_channel: i64,
+        permissions: &[(u64, i64)],
+    ) -> QueryResult<()>;
+
+    async fn query_channel_groups(&mut self) -> QueryResult<Vec<ChannelGroup>>;
+
+    async fn query_permission_list(&mut self) -> QueryResult<Vec<PermissionInfo>>;
+
+    async fn logout(&mut self) -> QueryResult<()>;
+}
@@ -0,0 +1,107 @@
+use crate::datastructures::Config;
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[cfg(unix)]
+type Hangup = tokio::signal::unix::Signal;
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(unix)]
+fn hangup() -> anyhow::Result<Hangup> {
+    use tokio::signal::unix::{signal, SignalKind};
+    Ok(signal(SignalKind::hangup())?)
+}
+
+#[cfg(not(unix))]
+fn hangup() -> anyhow::Result<Hangup> {
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn recv_hangup(hangup: &mut Hangup) {
+    hangup.recv().await;
+}
+
+#[cfg(not(unix))]
+async fn recv_hangup(_hangup: &mut Hangup) {
+    std::future::pending::<()>().await
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// Options shared by whole process are only read on startup
+fn warn_restart_required(current: &Config, new: &Config) {
+    let server_ids = |config: &Config| {
+        config
+            .servers()
+            .iter()
+            .map(|server| server.server_id())
+            .collect::<Vec<_>>()
+    };
+    let admin = |config: &Config| {
+        config
+            .admin()
+            .as_ref()
+            .map(|admin| (admin.listen().to_string(), admin.token().to_string()))
+    };
+    if server_ids(current) != server_ids(new)
+        || current.server().redis_server() != new.server().redis_server()
+        || current.server().storage() != new.server().storage()
+        || current.server().storage_path() != new.server().storage_path()
+        || current.misc().event_driven() != new.misc().event_driven()
+        || admin(current) != admin(new)
+        || current.metrics().as_ref().map(|metrics| metrics.listen())
+            != new.metrics().as_ref().map(|metrics| metrics.listen())
+        || current.audit().as_ref().map(|audit| audit.path())
+            != new.audit().as_ref().map(|audit| audit.path())
+    {
+        warn!("Change of server list, storage, event_driven, admin, metrics or audit requires restart");
+    }
+}
+
+pub async fn config_reloader(
+    path: PathBuf,
+    sender: watch::Sender<Config>,
+    watch_file: bool,
+) -> anyhow::Result<()> {
+    let mut hangup = hangup()?;
+    let mut last_modified = modified(&path);
+    let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+
+    loop {
+        let reason = tokio::select! {
+            _ = recv_hangup(&mut hangup) => "SIGHUP",
+            _ = ticker.tick(), if watch_file => {
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                "file changed"
+            }
+        };
+
+        match Config::try_from(path.as_path()) {
+            Ok(config) => {
+                info!("Reload configure file ({})", reason);
+                warn_restart_required(&sender.borrow(), &config);
+                if sender.send(config).is_err() {
+                    return Ok(());
+                }
+            }
+            Err(e) => error!(
+                "Reject new configure file ({}), keep current: {:?}",
+                reason, e
+            ),
+        }
+    }
+}
@@ -1,17 +1,47 @@
+use crate::codec::LineCodec;
+use crate::datastructures::config::{ChannelProperties, ChannelType};
 use crate::datastructures::{
-    Channel, Client, CreateChannel, QueryError, QueryResult, ServerInfo, WhoAmI,
+    Channel, ChannelGroup, Client, ClientDatabaseId, CreateChannel, PermissionInfo, QueryError,
+    QueryResult, ServerInfo, WhoAmI,
 };
-use crate::datastructures::{FromQueryString, QueryStatus};
+use crate::datastructures::{FromQueryString, Notifies, QueryStatus};
+use crate::metrics::METRICS;
+use crate::query::QueryConn;
+use crate::transport::{Endpoint, Transport};
 use anyhow::anyhow;
-use log::{error, warn};
-use std::time::Duration;
+use async_trait::async_trait;
+use log::{error, info, warn};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
 
-const BUFFER_SIZE: usize = 512;
+const BUFFER_SIZE: usize = 4096;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+const NOTIFY_REGISTER_PAYLOADS: [&str; 3] = [
+    "servernotifyregister event=server\n\r",
+    "servernotifyregister event=channel id=0\n\r",
+    "servernotifyregister event=textprivate\n\r",
+];
+
+// Everything needed to restore the session after reconnect
+#[derive(Clone, Debug)]
+struct Session {
+    endpoint: Endpoint,
+    login: Option<(String, String)>,
+    server_id: Option<i64>,
+    notifies_registered: bool,
+}
 
 pub struct SocketConn {
-    conn: TcpStream,
+    conn: Box<dyn Transport>,
+    codec: LineCodec,
+    session: Session,
+    broken: bool,
+    reconnected: bool,
+    // Kept across calls, reconnect may be cancelled while waiting
+    backoff: Duration,
+    last_active: Instant,
 }
 
 impl SocketConn {
@@ -26,7 +56,9 @@ impl SocketConn {
             if line.trim().starts_with("error ") {
                 let status = QueryStatus::try_from(line)?;
 
-                return status.into_result(content);
+                return status
+                    .into_result(content)
+                    .inspect_err(|e| METRICS.query_error(e.code()));
             }
         }
         Err(QueryError::static_empty_response())
@@ -37,44 +69,73 @@ impl SocketConn {
     ) -> QueryResult<Option<Vec<T>>> {
         let content = Self::decode_status(data)?;
 
+        let mut ret = None;
         for line in content.lines() {
             if !line.starts_with("error ") {
-                let mut v = Vec::new();
+                let v = ret.get_or_insert_with(Vec::new);
                 for element in line.split('|') {
                     v.push(T::from_query(element)?);
                 }
-                return Ok(Some(v));
             }
         }
-        Ok(None)
+        Ok(ret)
+    }
+
+    fn connection_lost(&mut self, error: anyhow::Error) -> anyhow::Error {
+        self.broken = true;
+        error
     }
 
-    async fn read_data(&mut self) -> anyhow::Result<Option<String>> {
+    // Read more data from connection into codec
+    async fn fill(&mut self, timeout: Duration) -> anyhow::Result<bool> {
         let mut buffer = [0u8; BUFFER_SIZE];
-        let mut ret = String::new();
+        let size = match tokio::time::timeout(timeout, self.conn.read(&mut buffer)).await {
+            Ok(Ok(size)) => size,
+            Ok(Err(e)) => {
+                return Err(self.connection_lost(anyhow!("Got error while read data: {:?}", e)))
+            }
+            Err(_) => return Ok(false),
+        };
+        if size == 0 {
+            return Err(self.connection_lost(anyhow!("Connection closed by server")));
+        }
+        self.codec.feed(&buffer[..size]);
+        Ok(true)
+    }
+
+    async fn read_data(&mut self) -> anyhow::Result<String> {
+        let deadline = Instant::now() + READ_TIMEOUT;
         loop {
-            let size = if let Ok(data) =
-                tokio::time::timeout(Duration::from_secs(2), self.conn.read(&mut buffer)).await
+            if let Some(response) = self.codec.next_response() {
+                return Ok(response);
+            }
+            // Response stream is out of sync if server stop answering, start over
+            if !self
+                .fill(deadline.saturating_duration_since(Instant::now()))
+                .await?
             {
-                match data {
-                    Ok(size) => size,
-                    Err(e) => return Err(anyhow!("Got error while read data: {:?}", e)),
-                }
-            } else {
-                return Ok(None);
-            };
+                return Err(self.connection_lost(anyhow!("Timeout while waiting response")));
+            }
+        }
+    }
 
-            ret.push_str(&String::from_utf8_lossy(&buffer[..size]));
-            if size < BUFFER_SIZE || (ret.contains("error id=") && ret.ends_with("\n\r")) {
-                break;
+    async fn read_banner(&mut self) -> anyhow::Result<()> {
+        let deadline = Instant::now() + READ_TIMEOUT;
+        while !self.codec.take_banner() {
+            if !self
+                .fill(deadline.saturating_duration_since(Instant::now()))
+                .await?
+            {
+                return Err(self.connection_lost(anyhow!("Timeout while waiting banner")));
             }
         }
-        Ok(Some(ret))
+        Ok(())
     }
 
     async fn write_data(&mut self, payload: &str) -> anyhow::Result<()> {
         debug_assert!(payload.ends_with("\n\r"));
-        self.conn
+        let ret = self
+            .conn
             .write(payload.as_bytes())
             .await
             .map(|size| {
@@ -87,19 +148,75 @@ impl SocketConn {
                     )
                 }
             })
-            .map_err(|e| anyhow!("Got error while send data: {:?}", e))?;
+            .map_err(|e| anyhow!("Got error while send data: {:?}", e));
         /*self.conn
         .flush()
         .await
         .map_err(|e| anyhow!("Got error while flush data: {:?}", e))?;*/
-        Ok(())
+        ret.map_err(|e| self.connection_lost(e))
     }
 
-    async fn write_and_read(&mut self, payload: &str) -> anyhow::Result<String> {
+    async fn exchange(&mut self, payload: &str) -> anyhow::Result<String> {
         self.write_data(payload).await?;
-        self.read_data()
-            .await?
-            .ok_or_else(|| anyhow!("Return data is None"))
+        let ret = self.read_data().await?;
+        self.last_active = Instant::now();
+        Ok(ret)
+    }
+
+    // Command which failed because of connection lost is not sent again,
+    // since it may already be executed by server.
+    async fn write_and_read(&mut self, payload: &str) -> anyhow::Result<String> {
+        if self.broken {
+            self.reconnect().await;
+        }
+        let start = Instant::now();
+        let ret = self.exchange(payload).await;
+        let command = payload.split_whitespace().next().unwrap_or_default();
+        METRICS.observe_query(command, start.elapsed());
+        ret
+    }
+
+    async fn restore_session(&mut self) -> anyhow::Result<()> {
+        let session = self.session.clone();
+        self.conn = session.endpoint.connect().await?;
+        self.codec.reset();
+        self.read_banner().await?;
+
+        if let Some((user, password)) = &session.login {
+            let payload = format!("login {} {}\n\r", user, password);
+            Self::decode_status(self.exchange(&payload).await?)?;
+        }
+        if let Some(server_id) = session.server_id {
+            Self::decode_status(self.exchange(&format!("use {}\n\r", server_id)).await?)?;
+        }
+        if session.notifies_registered {
+            for payload in NOTIFY_REGISTER_PAYLOADS {
+                Self::decode_status(self.exchange(payload).await?)?;
+            }
+        }
+        // Connection is usable only if whole session is restored
+        self.broken = false;
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) {
+        loop {
+            match self.restore_session().await {
+                Ok(_) => break,
+                Err(e) => {
+                    warn!(
+                        "Reconnect to {} failed, retry after {:?}: {:?}",
+                        self.session.endpoint, self.backoff, e
+                    );
+                    tokio::time::sleep(self.backoff).await;
+                    self.backoff = (self.backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                }
+            }
+        }
+        self.backoff = RECONNECT_BACKOFF_MIN;
+        info!("Reconnected to {}", self.session.endpoint);
+        METRICS.reconnected();
+        self.reconnected = true;
     }
 
     async fn basic_operation(&mut self, payload: &str) -> QueryResult<()> {
@@ -131,46 +248,114 @@ impl SocketConn {
         s.replace('\\', "\\\\")
             .replace(' ', "\\s")
             .replace('/', "\\/")
+            .replace('|', "\\p")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r")
+            .replace('\t', "\\t")
     }
 
-    pub async fn connect(server: &str, port: u16) -> anyhow::Result<Self> {
-        let conn = TcpStream::connect(format!("{}:{}", server, port))
+    pub async fn connect(endpoint: &Endpoint) -> anyhow::Result<Self> {
+        let conn = endpoint
+            .connect()
             .await
-            .map_err(|e| anyhow!("Got error while connect to {}:{} {:?}", server, port, e))?;
+            .map_err(|e| anyhow!("Got error while connect to {} {:?}", endpoint, e))?;
 
         //let bufreader = BufReader::new(conn);
         //conn.set_nonblocking(true).unwrap();
-        let mut self_ = Self { conn };
-
-        let content = self_
-            .read_data()
+        let mut self_ = Self {
+            conn,
+            codec: Default::default(),
+            session: Session {
+                endpoint: endpoint.clone(),
+                login: None,
+                server_id: None,
+                notifies_registered: false,
+            },
+            broken: false,
+            reconnected: false,
+            backoff: RECONNECT_BACKOFF_MIN,
+            last_active: Instant::now(),
+        };
+
+        self_
+            .read_banner()
             .await
-            .map_err(|e| anyhow!("Got error in connect while read content: {:?}", e))?;
-
-        if content.is_none() {
-            warn!("Read none data.");
-        }
+            .map_err(|e| anyhow!("Got error in connect while read banner: {:?}", e))?;
 
         Ok(self_)
     }
 
     pub async fn login(&mut self, user: &str, password: &str) -> QueryResult<()> {
         let payload = format!("login {} {}\n\r", user, password);
-        self.basic_operation(payload.as_str()).await
+        self.basic_operation(payload.as_str()).await?;
+        self.session.login = Some((user.to_string(), password.to_string()));
+        Ok(())
     }
 
     pub async fn select_server(&mut self, server_id: i64) -> QueryResult<()> {
         let payload = format!("use {}\n\r", server_id);
-        self.basic_operation(payload.as_str()).await
+        self.basic_operation(payload.as_str()).await?;
+        self.session.server_id = Some(server_id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl QueryConn for SocketConn {
+    async fn ensure_connected(&mut self) {
+        if self.broken {
+            self.reconnect().await;
+        }
     }
 
-    pub(crate) async fn who_am_i(&mut self) -> QueryResult<WhoAmI> {
+    // May be cancelled, so reconnect is left to `ensure_connected`
+    async fn wait_notifies(&mut self, timeout: Duration) -> anyhow::Result<Vec<Notifies>> {
+        if self.broken {
+            return Err(anyhow!("Connection is lost, reconnect is required"));
+        }
+        if !self.codec.has_notifies() && !self.fill(timeout).await? {
+            return Ok(vec![]);
+        }
+        while let Some(response) = self.codec.next_response() {
+            warn!(
+                "Got unexpected response while wait notifies: {:?}",
+                response
+            );
+        }
+        self.codec
+            .take_notifies()
+            .into_iter()
+            .map(|line| Notifies::from_line(&line))
+            .collect()
+    }
+
+    fn take_reconnected(&mut self) -> bool {
+        std::mem::take(&mut self.reconnected)
+    }
+
+    async fn register_notifies(&mut self) -> QueryResult<()> {
+        for payload in NOTIFY_REGISTER_PAYLOADS {
+            self.basic_operation(payload).await?;
+        }
+        self.session.notifies_registered = true;
+        Ok(())
+    }
+
+    fn last_active(&self) -> Instant {
+        self.last_active
+    }
+
+    async fn keepalive(&mut self) -> QueryResult<()> {
+        self.basic_operation("version\n\r").await
+    }
+
+    async fn who_am_i(&mut self) -> QueryResult<WhoAmI> {
         self.query_operation_non_error("whoami\n\r")
             .await
             .map(|mut v| v.remove(0))
     }
 
-    pub(crate) async fn send_text_message(&mut self, clid: i64, text: &str) -> QueryResult<()> {
+    async fn send_text_message(&mut self, clid: i64, text: &str) -> QueryResult<()> {
         let payload = format!(
             "sendtextmessage targetmode=1 target={clid} msg={text}\n\r",
             clid = clid,
@@ -179,26 +364,44 @@ impl SocketConn {
         self.basic_operation(&payload).await
     }
 
-    pub(crate) async fn query_server_info(&mut self) -> QueryResult<ServerInfo> {
+    async fn query_server_info(&mut self) -> QueryResult<ServerInfo> {
         self.query_operation_non_error("serverinfo\n\r")
             .await
             .map(|mut v| v.remove(0))
     }
 
-    #[allow(dead_code)]
     async fn query_channels(&mut self) -> QueryResult<Vec<Channel>> {
         self.query_operation_non_error("channellist\n\r").await
     }
 
-    pub(crate) async fn create_channel(
+    async fn delete_channel(&mut self, cid: i64, force: bool) -> QueryResult<()> {
+        let payload = format!(
+            "channeldelete cid={cid} force={force}\n\r",
+            cid = cid,
+            force = force as u8
+        );
+        self.basic_operation(&payload).await
+    }
+
+    async fn create_channel(
         &mut self,
         name: &str,
         pid: i64,
+        channel_type: Option<ChannelType>,
+        properties: &ChannelProperties,
     ) -> QueryResult<Option<CreateChannel>> {
         let payload = format!(
-            "channelcreate channel_name={name} cpid={pid} channel_codec_quality=6\n\r",
+            "channelcreate channel_name={name} cpid={pid}{properties}{flag}\n\r",
             name = Self::escape(name),
-            pid = pid
+            pid = pid,
+            properties = properties
+                .to_pairs()
+                .iter()
+                .map(|(k, v)| format!(" {}={}", k, Self::escape(v)))
+                .collect::<String>(),
+            flag = channel_type
+                .map(|t| format!(" {}=1", t.flag()))
+                .unwrap_or_default()
         );
         /*let ret = self.query_operation(payload.as_str()).await?;
         Ok(ret.map(|mut v| v.remove(0)))*/
@@ -207,15 +410,39 @@ impl SocketConn {
             .map(|r| r.map(|mut v| v.swap_remove(0)))
     }
 
-    pub(crate) async fn query_clients(&mut self) -> QueryResult<Vec<Client>> {
-        self.query_operation_non_error("clientlist\n\r").await
+    async fn edit_channel(&mut self, cid: i64, properties: &[(&str, String)]) -> QueryResult<()> {
+        let payload = format!(
+            "channeledit cid={cid}{properties}\n\r",
+            cid = cid,
+            properties = properties
+                .iter()
+                .map(|(k, v)| format!(" {}={}", k, Self::escape(v)))
+                .collect::<String>()
+        );
+        self.basic_operation(&payload).await
     }
 
-    pub(crate) async fn move_client_to_channel(
-        &mut self,
-        clid: i64,
-        target_channel: i64,
-    ) -> QueryResult<()> {
+    async fn query_clients(&mut self) -> QueryResult<Vec<Client>> {
+        self.query_operation_non_error("clientlist -uid\n\r").await
+    }
+
+    async fn query_client_database_id(&mut self, uid: &str) -> QueryResult<ClientDatabaseId> {
+        let payload = format!("clientgetdbidfromuid cluid={}\n\r", Self::escape(uid));
+        self.query_operation_non_error(&payload)
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    async fn kick_client_from_channel(&mut self, clid: i64, reason: &str) -> QueryResult<()> {
+        let payload = format!(
+            "clientkick clid={clid} reasonid=4 reasonmsg={reason}\n\r",
+            clid = clid,
+            reason = Self::escape(reason)
+        );
+        self.basic_operation(&payload).await
+    }
+
+    async fn move_client_to_channel(&mut self, clid: i64, target_channel: i64) -> QueryResult<()> {
         let payload = format!(
             "clientmove clid={clid} cid={cid}\n\r",
             clid = clid,
@@ -224,7 +451,7 @@ impl SocketConn {
         self.basic_operation(payload.as_str()).await
     }
 
-    pub(crate) async fn set_client_channel_group(
+    async fn set_client_channel_group(
         &mut self,
         client_database_id: i64,
         channel_id: i64,
@@ -239,24 +466,73 @@ impl SocketConn {
         self.basic_operation(&payload).await
     }
 
-    pub(crate) async fn add_channel_permission(
+    async fn add_channel_permission(
         &mut self,
         target_channel: i64,
         permissions: &[(u64, i64)],
     ) -> QueryResult<()> {
         let payload = format!(
-            "channeladdperm cid={} {}",
+            "channeladdperm cid={} {}\n\r",
             target_channel,
             permissions
                 .iter()
-                .map(|(k, v)| format!("permid={} permvalue={}\n\r", k, v))
+                .map(|(k, v)| format!("permid={} permvalue={}", k, v))
                 .collect::<Vec<String>>()
                 .join("|")
         );
         self.basic_operation(&payload).await
     }
 
-    pub(crate) async fn logout(&mut self) -> QueryResult<()> {
+    async fn query_channel_groups(&mut self) -> QueryResult<Vec<ChannelGroup>> {
+        self.query_operation_non_error("channelgrouplist\n\r").await
+    }
+
+    async fn query_permission_list(&mut self) -> QueryResult<Vec<PermissionInfo>> {
+        self.query_operation_non_error("permissionlist\n\r").await
+    }
+
+    async fn logout(&mut self) -> QueryResult<()> {
         self.basic_operation("quit\n\r").await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::mock::MockServer;
+    use crate::query::QueryConn;
+    use crate::socketlib::SocketConn;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test() {
+        let mock = MockServer::start().await;
+        let mut conn = SocketConn::connect(&mock.endpoint()).await.unwrap();
+        conn.login("serveradmin", "114514").await.unwrap();
+        conn.select_server(1).await.unwrap();
+        conn.register_notifies().await.unwrap();
+
+        mock.push_disconnect("version");
+        assert!(conn.keepalive().await.is_err());
+        assert!(conn.wait_notifies(Duration::from_millis(10)).await.is_err());
+        assert!(!conn.take_reconnected());
+
+        // Connection is still broken if session is not fully restored
+        mock.push_error("use", 1024, "invalid serverID");
+        mock.take_commands();
+        conn.ensure_connected().await;
+        assert!(conn.take_reconnected());
+        let commands = mock.take_commands();
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|command| command.starts_with("login"))
+                .count(),
+            2
+        );
+        assert_eq!(
+            commands.last().unwrap(),
+            "servernotifyregister event=textprivate"
+        );
+        conn.keepalive().await.unwrap();
+    }
+}
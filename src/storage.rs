@@ -0,0 +1,438 @@
+use crate::datastructures::config::StorageType;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const KEY_PREFIX: &str = "ts_autochannel_";
+
+pub fn channel_key(client_database_id: i64, server_id: &str, pid: i64) -> String {
+    format!(
+        "{}{}_{server_id}_{pid}",
+        KEY_PREFIX,
+        client_database_id,
+        server_id = server_id,
+        pid = pid
+    )
+}
+
+/// Database id of channel owner, which is the second part of key.
+pub fn key_client(key: &str) -> Option<i64> {
+    key.strip_prefix(KEY_PREFIX)
+        .and_then(|key| key.split_once('_'))
+        .and_then(|(cldbid, _)| cldbid.parse().ok())
+}
+
+/// Channel id of parent channel, which is the last part of key.
+pub fn key_parent(key: &str) -> Option<i64> {
+    key.rsplit_once('_').and_then(|(_, pid)| pid.parse().ok())
+}
+
+// Server unique identifier of key, which is the third part of key.
+fn key_server(key: &str) -> Option<&str> {
+    key.strip_prefix(KEY_PREFIX)
+        .and_then(|key| key.rsplit_once('_'))
+        .and_then(|(key, _)| key.split_once('_'))
+        .map(|(_, server_id)| server_id)
+}
+
+pub type SharedStorage = Arc<Mutex<Box<dyn Storage>>>;
+
+pub async fn open(
+    storage: StorageType,
+    redis_server: &str,
+    path: &Path,
+) -> anyhow::Result<Box<dyn Storage>> {
+    Ok(match storage {
+        StorageType::Redis => Box::new(RedisStorage::connect(redis_server).await?),
+        StorageType::Sqlite => {
+            let path = path.to_path_buf();
+            Box::new(blocking(move || SqliteStorage::open(&path)).await?)
+        }
+        StorageType::Json => {
+            let path = path.to_path_buf();
+            Box::new(blocking(move || JsonStorage::open(&path)).await?)
+        }
+        StorageType::Memory => Box::new(MemoryStorage::default()),
+    })
+}
+
+// Run file or database I/O on blocking thread, so async workers are not stalled
+async fn blocking<T, F>(f: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| anyhow!("Blocking task error: {:?}", e))?
+}
+
+/// Mapping from `ts_autochannel_{cldbid}_{server_id}_{pid}` to created channel id.
+#[async_trait]
+pub trait Storage: Send {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>>;
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()>;
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()>;
+
+    /// Move value of `from` to `to` atomically, return false if `to` exists.
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool>;
+
+    /// All keys belongs to `server_id`.
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>>;
+}
+
+// Lock for each operation only, so servers never wait for ServerQuery I/O of each other
+#[async_trait]
+impl Storage for SharedStorage {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>> {
+        self.lock().await.get(key).await
+    }
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()> {
+        self.lock().await.set(key, cid).await
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        self.lock().await.del(key).await
+    }
+
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool> {
+        self.lock().await.transfer(from, to).await
+    }
+
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>> {
+        self.lock().await.keys(server_id).await
+    }
+}
+
+pub struct RedisStorage {
+    conn: redis::aio::Connection,
+}
+
+impl RedisStorage {
+    pub async fn connect(redis_server: &str) -> anyhow::Result<Self> {
+        let redis = redis::Client::open(redis_server)
+            .map_err(|e| anyhow!("Connect redis server error! {:?}", e))?;
+        let conn = redis
+            .get_async_connection()
+            .await
+            .map_err(|e| anyhow!("Get redis connection error: {:?}", e))?;
+        Ok(Self { conn })
+    }
+}
+
+#[async_trait]
+impl Storage for RedisStorage {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>> {
+        Ok(self.conn.get(key).await?)
+    }
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()> {
+        Ok(self.conn.set(key, cid).await?)
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        Ok(self.conn.del(key).await?)
+    }
+
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool> {
+        Ok(self.conn.rename_nx(from, to).await?)
+    }
+
+    // SCAN instead of KEYS, which blocks redis server until all keys are walked
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>> {
+        let mut iter = self
+            .conn
+            .scan_match(format!("{}*_{}_*", KEY_PREFIX, server_id))
+            .await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+}
+
+/// Mapping is lost after restart.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    map: HashMap<String, i64>,
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>> {
+        Ok(self.map.get(key).copied())
+    }
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()> {
+        self.map.insert(key.to_string(), cid);
+        Ok(())
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        self.map.remove(key);
+        Ok(())
+    }
+
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool> {
+        if self.map.contains_key(to) {
+            return Ok(false);
+        }
+        let cid = self
+            .map
+            .remove(from)
+            .ok_or_else(|| anyhow!("Key {} not found", from))?;
+        self.map.insert(to.to_string(), cid);
+        Ok(true)
+    }
+
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .map
+            .keys()
+            .filter(|key| key_server(key) == Some(server_id))
+            .cloned()
+            .collect())
+    }
+}
+
+pub struct SqliteStorage {
+    conn: Arc<std::sync::Mutex<rusqlite::Connection>>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = rusqlite::Connection::open(path)
+            .map_err(|e| anyhow!("Open sqlite database {} error: {:?}", path.display(), e))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS autochannel (key TEXT PRIMARY KEY, cid INTEGER NOT NULL)",
+            [],
+        )?;
+        Ok(Self {
+            conn: Arc::new(std::sync::Mutex::new(conn)),
+        })
+    }
+
+    async fn run<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.conn.clone();
+        blocking(move || f(&mut conn.lock().unwrap())).await
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>> {
+        use rusqlite::OptionalExtension;
+        let key = key.to_string();
+        self.run(move |conn| {
+            Ok(conn
+                .query_row("SELECT cid FROM autochannel WHERE key = ?1", [key], |row| {
+                    row.get(0)
+                })
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()> {
+        let key = key.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO autochannel (key, cid) VALUES (?1, ?2)",
+                rusqlite::params![key, cid],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        let key = key.to_string();
+        self.run(move |conn| {
+            conn.execute("DELETE FROM autochannel WHERE key = ?1", [key])?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool> {
+        let (from, to) = (from.to_string(), to.to_string());
+        self.run(move |conn| {
+            let transaction = conn.transaction()?;
+            let exists: bool = transaction.query_row(
+                "SELECT EXISTS(SELECT 1 FROM autochannel WHERE key = ?1)",
+                [&to],
+                |row| row.get(0),
+            )?;
+            if exists {
+                return Ok(false);
+            }
+            if transaction.execute(
+                "UPDATE autochannel SET key = ?2 WHERE key = ?1",
+                [&from, &to],
+            )? == 0
+            {
+                return Err(anyhow!("Key {} not found", from));
+            }
+            transaction.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>> {
+        let keys = self
+            .run(|conn| {
+                let mut statement = conn.prepare("SELECT key FROM autochannel")?;
+                let keys = statement
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(keys)
+            })
+            .await?;
+        Ok(keys
+            .into_iter()
+            .filter(|key| key_server(key) == Some(server_id))
+            .collect())
+    }
+}
+
+/// Whole mapping is kept in memory and written back to file on every change.
+pub struct JsonStorage {
+    path: PathBuf,
+    map: HashMap<String, i64>,
+}
+
+impl JsonStorage {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let map = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(path)?)
+                .map_err(|e| anyhow!("Parse storage file {} error: {:?}", path.display(), e))?
+        } else {
+            Default::default()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            map,
+        })
+    }
+
+    // Write to temporary file then rename, so file is never half written.
+    // Changes are made on a copy of map, which replaces memory only after file is written.
+    async fn save(&mut self, map: HashMap<String, i64>) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(&map)?;
+        let path = self.path.clone();
+        blocking(move || {
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, content)?;
+            std::fs::rename(&tmp, &path)?;
+            Ok(())
+        })
+        .await?;
+        self.map = map;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for JsonStorage {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<i64>> {
+        Ok(self.map.get(key).copied())
+    }
+
+    async fn set(&mut self, key: &str, cid: i64) -> anyhow::Result<()> {
+        let mut map = self.map.clone();
+        map.insert(key.to_string(), cid);
+        self.save(map).await
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        let mut map = self.map.clone();
+        if map.remove(key).is_some() {
+            self.save(map).await?;
+        }
+        Ok(())
+    }
+
+    async fn transfer(&mut self, from: &str, to: &str) -> anyhow::Result<bool> {
+        if self.map.contains_key(to) {
+            return Ok(false);
+        }
+        let mut map = self.map.clone();
+        let cid = map
+            .remove(from)
+            .ok_or_else(|| anyhow!("Key {} not found", from))?;
+        map.insert(to.to_string(), cid);
+        self.save(map).await?;
+        Ok(true)
+    }
+
+    async fn keys(&mut self, server_id: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .map
+            .keys()
+            .filter(|key| key_server(key) == Some(server_id))
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datastructures::config::StorageType;
+    use crate::storage::{channel_key, key_client, key_parent, open, JsonStorage, Storage};
+
+    #[tokio::test]
+    async fn test() {
+        let dir = std::env::temp_dir().join(format!("ts-autochannel-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (storage_type, path) in [
+            (StorageType::Memory, dir.join("none")),
+            (StorageType::Sqlite, dir.join("storage.db")),
+            (StorageType::Json, dir.join("storage.json")),
+        ] {
+            let key = channel_key(10, "uid", 1);
+            let mut storage = open(storage_type, "", &path).await.unwrap();
+            storage.set(&key, 20).await.unwrap();
+            storage.set(&channel_key(10, "other", 1), 21).await.unwrap();
+            assert_eq!(storage.get(&key).await.unwrap(), Some(20));
+            assert_eq!(storage.keys("uid").await.unwrap(), vec![key.clone()]);
+            assert_eq!(key_parent(&key), Some(1));
+            assert_eq!(key_client(&key), Some(10));
+
+            if storage_type != StorageType::Memory {
+                let mut storage = open(storage_type, "", &path).await.unwrap();
+                assert_eq!(storage.get(&key).await.unwrap(), Some(20));
+            }
+            let new_key = channel_key(11, "uid", 1);
+            assert!(storage.transfer(&key, &new_key).await.unwrap());
+            assert_eq!(storage.get(&new_key).await.unwrap(), Some(20));
+            storage.set(&key, 22).await.unwrap();
+            assert!(!storage.transfer(&key, &new_key).await.unwrap());
+            storage.del(&key).await.unwrap();
+            assert_eq!(storage.get(&key).await.unwrap(), None);
+        }
+
+        // Failed write keeps memory same as file
+        let sub = dir.join("sub");
+        std::fs::create_dir_all(&sub).unwrap();
+        let mut storage = JsonStorage::open(&sub.join("storage.json")).unwrap();
+        storage.set("key", 20).await.unwrap();
+        std::fs::remove_dir_all(&sub).unwrap();
+        assert!(storage.set("key", 21).await.is_err());
+        assert!(storage.del("key").await.is_err());
+        assert_eq!(storage.get("key").await.unwrap(), Some(20));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
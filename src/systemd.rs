@@ -0,0 +1,176 @@
+use crate::metrics::METRICS;
+use log::{error, warn};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+// Last time each server went through its main loop
+static HEARTBEATS: Lazy<Mutex<HashMap<i64, Instant>>> = Lazy::new(Default::default);
+
+pub fn heartbeat(server_id: i64) {
+    HEARTBEATS.lock().unwrap().insert(server_id, Instant::now());
+}
+
+/// Watchdog timeout requested by systemd (`WatchdogSec=`), None if disabled.
+pub fn watchdog_timeout() -> Option<Duration> {
+    // Watchdog is meant for another process if pid does not match
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    std::env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+#[cfg(unix)]
+pub struct Notifier {
+    socket: std::os::unix::net::UnixDatagram,
+    addr: std::os::unix::net::SocketAddr,
+}
+
+#[cfg(unix)]
+impl Notifier {
+    /// Connect to `$NOTIFY_SOCKET`, None if not started by systemd (or `Type=` is not `notify`).
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        match std::env::var("NOTIFY_SOCKET") {
+            Ok(path) if !path.is_empty() => Self::connect(&path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn connect(path: &str) -> anyhow::Result<Self> {
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+        let addr = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)?
+            }
+            _ => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+        })
+    }
+
+    pub fn notify(&self, state: &str) -> anyhow::Result<()> {
+        self.socket.send_to_addr(state.as_bytes(), &self.addr)?;
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+pub struct Notifier;
+
+#[cfg(not(unix))]
+impl Notifier {
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    pub fn notify(&self, _state: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Send `READY=1` once every server is serving, then keep status updated
+/// and ping watchdog as long as no server is stuck.
+pub async fn serve(notifier: Arc<Notifier>, servers: Vec<i64>, watchdog: Option<Duration>) {
+    let interval = watchdog.map_or(STATUS_INTERVAL, |timeout| STATUS_INTERVAL.min(timeout / 2));
+    let mut ready = false;
+    loop {
+        let (started, alive) = {
+            let heartbeats = HEARTBEATS.lock().unwrap();
+            let started = servers.iter().all(|id| heartbeats.contains_key(id));
+            let alive = watchdog.is_none_or(|timeout| {
+                servers.iter().all(|id| {
+                    heartbeats
+                        .get(id)
+                        .is_some_and(|last| last.elapsed() < timeout)
+                })
+            });
+            (started, alive)
+        };
+
+        let mut state = Vec::new();
+        if started && !ready {
+            ready = true;
+            state.push("READY=1".to_string());
+        }
+        state.push(format!("STATUS={}", METRICS.summary()));
+        if ready && watchdog.is_some() {
+            if alive {
+                state.push("WATCHDOG=1".to_string());
+            } else {
+                warn!("Some server does not respond, skip watchdog ping");
+            }
+        }
+        if let Err(e) = notifier.notify(&state.join("\n")) {
+            error!("Send notify to systemd error: {:?}", e);
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use crate::systemd::{heartbeat, serve, Notifier};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::UnixDatagram;
+
+    async fn recv(receiver: &UnixDatagram) -> String {
+        let mut buf = [0; 1024];
+        let size = tokio::time::timeout(Duration::from_secs(2), receiver.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        String::from_utf8_lossy(&buf[..size]).to_string()
+    }
+
+    #[tokio::test]
+    async fn test() {
+        let path = std::env::temp_dir().join(format!("ts-notify-{}.sock", std::process::id()));
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::connect(path.to_str().unwrap()).unwrap();
+
+        heartbeat(101);
+        let handler = tokio::spawn(serve(
+            Arc::new(notifier),
+            vec![101, 102],
+            Some(Duration::from_millis(400)),
+        ));
+
+        // Server 102 is not started yet
+        let state = recv(&receiver).await;
+        assert!(state.starts_with("STATUS=Monitoring"), "{}", state);
+
+        heartbeat(102);
+        let state = recv(&receiver).await;
+        assert!(state.starts_with("READY=1\nSTATUS="), "{}", state);
+        assert!(state.ends_with("\nWATCHDOG=1"), "{}", state);
+
+        // Server 101 is stuck
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        heartbeat(102);
+        let mut state = recv(&receiver).await;
+        while state.contains("WATCHDOG=1") {
+            heartbeat(102);
+            state = recv(&receiver).await;
+        }
+        assert!(!state.contains("READY=1"), "{}", state);
+
+        handler.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
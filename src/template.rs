@@ -0,0 +1,104 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const CHANNEL_NAME_MAX_LENGTH: usize = 40;
+pub const DEFAULT_NAME_TEMPLATE: &str = "{nickname}'s channel";
+
+pub struct NameTemplate<'a> {
+    template: &'a str,
+    nickname: &'a str,
+    database_id: i64,
+    parent: &'a str,
+    date: String,
+}
+
+impl<'a> NameTemplate<'a> {
+    pub fn new(template: &'a str, nickname: &'a str, database_id: i64, parent: &'a str) -> Self {
+        Self {
+            template,
+            nickname,
+            database_id,
+            parent,
+            date: today(),
+        }
+    }
+
+    pub fn need_parent(template: &str) -> bool {
+        template.contains("{parent}")
+    }
+
+    fn substitute(&self, nickname: &str, counter: usize) -> String {
+        self.template
+            .replace("{nickname}", nickname)
+            .replace("{database_id}", &self.database_id.to_string())
+            .replace("{counter}", &counter.to_string())
+            .replace("{parent}", self.parent)
+            .replace("{date}", &self.date)
+    }
+
+    /// Render channel name for the `counter`-th attempt (starts from 1).
+    ///
+    /// If template has no `{counter}` placeholder, ` (counter)` will be appended on collision.
+    /// Nickname will be shortened first if result exceeds TeamSpeak channel name limit.
+    pub fn render(&self, counter: usize) -> String {
+        let suffix = if counter > 1 && !self.template.contains("{counter}") {
+            format!(" ({})", counter)
+        } else {
+            String::new()
+        };
+        let limit = CHANNEL_NAME_MAX_LENGTH.saturating_sub(suffix.chars().count());
+
+        let mut name = self.substitute(self.nickname, counter);
+        let length = name.chars().count();
+        let occurs = self.template.matches("{nickname}").count();
+        if length > limit && occurs > 0 {
+            let excess = (length - limit).div_ceil(occurs);
+            let keep = self.nickname.chars().count().saturating_sub(excess);
+            let nickname = self.nickname.chars().take(keep).collect::<String>();
+            name = self.substitute(nickname.trim_end(), counter);
+        }
+        if name.chars().count() > limit {
+            name = name.chars().take(limit).collect();
+        }
+        name + &suffix
+    }
+}
+
+// Current date (UTC) in YYYY-MM-DD format
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or_default() as i64;
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::template::{NameTemplate, CHANNEL_NAME_MAX_LENGTH, DEFAULT_NAME_TEMPLATE};
+
+    #[test]
+    fn test() {
+        let template = NameTemplate::new(DEFAULT_NAME_TEMPLATE, "Alice", 5, "Lobby");
+        assert_eq!(template.render(1), "Alice's channel");
+        assert_eq!(template.render(2), "Alice's channel (2)");
+
+        let template = NameTemplate::new("{parent} #{counter} ({database_id})", "Bob", 7, "Game");
+        assert_eq!(template.render(3), "Game #3 (7)");
+
+        let nickname = "A".repeat(CHANNEL_NAME_MAX_LENGTH);
+        let template = NameTemplate::new(DEFAULT_NAME_TEMPLATE, &nickname, 5, "");
+        let name = template.render(12);
+        assert_eq!(name.chars().count(), CHANNEL_NAME_MAX_LENGTH);
+        assert!(name.ends_with("'s channel (12)"));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::audit::{self, Action, Record};
+use crate::query::QueryConn;
+use crate::storage::{channel_key, key_client, key_parent, Storage};
+use anyhow::anyhow;
+use log::{error, info};
+
+/// Find key of channel `cid`, which contains current owner.
+pub async fn find_owner(
+    storage: &mut dyn Storage,
+    server_id: &str,
+    cid: i64,
+) -> anyhow::Result<Option<String>> {
+    for key in storage.keys(server_id).await? {
+        if storage.get(&key).await? == Some(cid) {
+            return Ok(Some(key));
+        }
+    }
+    Ok(None)
+}
+
+// Online client by nickname first, then treat target as unique id
+async fn resolve_target(conn: &mut dyn QueryConn, target: &str) -> anyhow::Result<i64> {
+    let clients = conn
+        .query_clients()
+        .await
+        .map_err(|e| anyhow!("Got error while query clients: {:?}", e))?;
+    if let Some(client) = clients.iter().find(|client| {
+        client.client_type() == 0 && client.client_nickname().eq_ignore_ascii_case(target)
+    }) {
+        return Ok(client.client_database_id());
+    }
+    conn.query_client_database_id(target)
+        .await
+        .map(|ret| ret.cldbid())
+        .map_err(|_| anyhow!("Can't find client {}", target))
+}
+
+/// Transfer channel `cid` owned by `key` to `target` (nickname or unique id).
+///
+/// New owner gets `privilege_group`, old owner is demoted to server default channel group.
+pub async fn transfer(
+    conn: &mut dyn QueryConn,
+    storage: &mut dyn Storage,
+    server_id: &str,
+    privilege_group: i64,
+    key: &str,
+    cid: i64,
+    target: &str,
+) -> anyhow::Result<()> {
+    let (old_owner, pid) = key_client(key)
+        .zip(key_parent(key))
+        .ok_or_else(|| anyhow!("Invalid key: {}", key))?;
+    let new_owner = resolve_target(conn, target).await?;
+    if new_owner == old_owner {
+        return Err(anyhow!("{} already owns this channel", target));
+    }
+    let new_key = channel_key(new_owner, server_id, pid);
+    if storage.get(&new_key).await?.is_some() {
+        return Err(anyhow!("{} already owns a channel here", target));
+    }
+
+    let default_group = conn
+        .query_server_info()
+        .await
+        .map_err(|e| anyhow!("Query server info error: {:?}", e))?
+        .virtualserver_default_channel_group();
+
+    // Move mapping first, so nobody is granted if target got a channel meanwhile
+    if !storage.transfer(key, &new_key).await? {
+        return Err(anyhow!("{} already owns a channel here", target));
+    }
+
+    let ret = conn
+        .set_client_channel_group(new_owner, cid, privilege_group)
+        .await;
+    Record::new(Action::Transfer, server_id, cid, audit::code(&ret))
+        .owner(Some(new_owner))
+        .detail(format!("from {}", old_owner))
+        .write();
+    if let Err(e) = ret {
+        // Channel still belongs to old owner
+        match storage.transfer(&new_key, key).await {
+            Ok(true) => {}
+            Ok(false) => error!("Can't restore mapping {}, it is taken", key),
+            Err(e) => error!("Got error while restore mapping {}: {:?}", key, e),
+        }
+        return Err(anyhow!("Got error while set client channel group: {:?}", e));
+    }
+
+    // Ownership is already moved, failure of demote is not fatal
+    conn.set_client_channel_group(old_owner, cid, default_group)
+        .await
+        .map_err(|e| error!("Got error while demote old owner {}: {:?}", old_owner, e))
+        .ok();
+
+    info!(
+        "Transfer channel {} from {} to {}",
+        cid, old_owner, new_owner
+    );
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use anyhow::anyhow;
+use std::fmt::{Display, Formatter};
+use std::io::{ErrorKind, Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+const SSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+const SSH_BUFFER_SIZE: usize = 4096;
+
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+#[derive(Clone, Debug)]
+pub enum Endpoint {
+    Tcp {
+        server: String,
+        port: u16,
+    },
+    Ssh {
+        server: String,
+        port: u16,
+        user: String,
+        password: String,
+    },
+}
+
+impl Endpoint {
+    pub async fn connect(&self) -> anyhow::Result<Box<dyn Transport>> {
+        match self {
+            Endpoint::Tcp { server, port } => Ok(Box::new(
+                TcpStream::connect(format!("{}:{}", server, port)).await?,
+            )),
+            Endpoint::Ssh {
+                server,
+                port,
+                user,
+                password,
+            } => Ok(Box::new(
+                SshStream::connect(server.clone(), *port, user.clone(), password.clone()).await?,
+            )),
+        }
+    }
+
+    // Login is done by SSH authentication
+    pub fn need_login(&self) -> bool {
+        matches!(self, Endpoint::Tcp { .. })
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Tcp { server, port } => write!(f, "{}:{}", server, port),
+            Endpoint::Ssh { server, port, .. } => write!(f, "ssh://{}:{}", server, port),
+        }
+    }
+}
+
+// libssh2 is blocking, so SSH channel is driven by a dedicated thread,
+// and data is exchanged with async side through channels.
+pub struct SshStream {
+    receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl SshStream {
+    async fn connect(
+        server: String,
+        port: u16,
+        user: String,
+        password: String,
+    ) -> anyhow::Result<Self> {
+        let (ready_sender, ready_receiver) = tokio::sync::oneshot::channel();
+        let (incoming_sender, receiver) = mpsc::unbounded_channel();
+        let (sender, outgoing_receiver) = mpsc::unbounded_channel();
+
+        std::thread::spawn(move || {
+            let channel = match Self::open_channel(&server, port, &user, &password) {
+                Ok(ret) => {
+                    ready_sender.send(Ok(())).ok();
+                    ret
+                }
+                Err(e) => {
+                    ready_sender.send(Err(e)).ok();
+                    return;
+                }
+            };
+            Self::drive(channel, incoming_sender, outgoing_receiver);
+        });
+
+        ready_receiver
+            .await
+            .map_err(|_| anyhow!("SSH thread exited unexpectedly"))??;
+
+        Ok(Self {
+            receiver,
+            sender,
+            pending: Vec::new(),
+        })
+    }
+
+    fn open_channel(
+        server: &str,
+        port: u16,
+        user: &str,
+        password: &str,
+    ) -> anyhow::Result<(ssh2::Session, ssh2::Channel)> {
+        let tcp = std::net::TcpStream::connect(format!("{}:{}", server, port))?;
+        let mut session = ssh2::Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        session
+            .userauth_password(user, password)
+            .map_err(|e| anyhow!("SSH authentication failed: {:?}", e))?;
+        let mut channel = session.channel_session()?;
+        channel.shell()?;
+        session.set_blocking(false);
+        Ok((session, channel))
+    }
+
+    fn drive(
+        (_session, mut channel): (ssh2::Session, ssh2::Channel),
+        sender: mpsc::UnboundedSender<Vec<u8>>,
+        mut receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    ) {
+        let mut buffer = [0u8; SSH_BUFFER_SIZE];
+        loop {
+            let mut idle = true;
+            match channel.read(&mut buffer) {
+                Ok(0) if channel.eof() => break,
+                Ok(0) => {}
+                Ok(size) => {
+                    idle = false;
+                    if sender.send(buffer[..size].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+
+            match receiver.try_recv() {
+                Ok(data) => {
+                    idle = false;
+                    if Self::write_all(&mut channel, &data).is_err() {
+                        break;
+                    }
+                }
+                Err(mpsc::error::TryRecvError::Empty) => {}
+                Err(mpsc::error::TryRecvError::Disconnected) => break,
+            }
+
+            if idle {
+                std::thread::sleep(SSH_POLL_INTERVAL);
+            }
+        }
+        channel.close().ok();
+    }
+
+    fn write_all(channel: &mut ssh2::Channel, mut data: &[u8]) -> std::io::Result<()> {
+        while !data.is_empty() {
+            match channel.write(data) {
+                Ok(size) => data = &data[size..],
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(SSH_POLL_INTERVAL)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AsyncRead for SshStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.pending.is_empty() {
+            match self.receiver.poll_recv(cx) {
+                Poll::Ready(Some(data)) => self.pending = data,
+                // Thread exited, treat as EOF
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let size = buf.remaining().min(self.pending.len());
+        buf.put_slice(&self.pending[..size]);
+        self.pending.drain(..size);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for SshStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(
+            self.sender
+                .send(buf.to_vec())
+                .map(|_| buf.len())
+                .map_err(|_| std::io::Error::new(ErrorKind::BrokenPipe, "SSH channel closed")),
+        )
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,162 @@
+use crate::datastructures::config::Server;
+use crate::datastructures::Config;
+use crate::query::QueryConn;
+use anyhow::anyhow;
+use std::collections::{HashMap, HashSet};
+
+/// Check configure of `server` against live server, return every problem found.
+pub async fn validate(
+    conn: &mut dyn QueryConn,
+    config: &Config,
+    server: &Server,
+) -> anyhow::Result<Vec<String>> {
+    let mut problems = Vec::new();
+
+    let channels = conn
+        .query_channels()
+        .await
+        .map_err(|e| anyhow!("Query channels error: {:?}", e))?
+        .into_iter()
+        .map(|channel| channel.cid())
+        .collect::<HashSet<_>>();
+    for cid in server.channels() {
+        if !channels.contains(&cid) {
+            problems.push(format!("Monitored channel {} not found", cid));
+        }
+    }
+
+    let groups = conn
+        .query_channel_groups()
+        .await
+        .map_err(|e| anyhow!("Query channel groups error: {:?}", e))?;
+    if !groups
+        .iter()
+        .any(|group| group.cgid() == server.privilege_group_id())
+    {
+        problems.push(format!(
+            "Channel group {} (privilege_group_id) not found",
+            server.privilege_group_id()
+        ));
+    }
+
+    let permission_list = conn
+        .query_permission_list()
+        .await
+        .map_err(|e| anyhow!("Query permission list error: {:?}", e))?
+        .into_iter()
+        .filter(|permission| !permission.permname().is_empty())
+        .collect::<Vec<_>>();
+    let ids = permission_list
+        .iter()
+        .map(|permission| permission.permid())
+        .collect::<HashSet<_>>();
+    let names = permission_list
+        .iter()
+        .map(|permission| (permission.permname().to_string(), permission.permid()))
+        .collect::<HashMap<_, _>>();
+
+    for name in config.permission_names(server) {
+        if !names.contains_key(name) {
+            problems.push(format!("Unknown permission name: {}", name));
+        }
+    }
+
+    // Unknown names are reported above, check the rest
+    let mut channel_permissions = config
+        .known_channel_permissions(server, &names)
+        .into_iter()
+        .collect::<Vec<_>>();
+    channel_permissions.sort_unstable_by_key(|(cid, _)| *cid);
+    for (cid, permissions) in channel_permissions {
+        if !channels.contains(&cid) {
+            problems.push(format!("Channel {} in permissions not found", cid));
+        }
+        for (permid, _) in permissions {
+            if !ids.contains(&permid) {
+                problems.push(format!(
+                    "Unknown permission id {} of channel {}",
+                    permid, cid
+                ));
+            }
+        }
+    }
+    for (permid, _) in server.default_permission().resolve_known(&names) {
+        if !ids.contains(&permid) {
+            problems.push(format!(
+                "Unknown permission id {} in default_permission",
+                permid
+            ));
+        }
+    }
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datastructures::Config;
+    use crate::mock::MockServer;
+    use crate::socketlib::SocketConn;
+    use crate::validate::validate;
+
+    #[tokio::test]
+    async fn test() {
+        let config: Config = toml::from_str(
+            r#"
+[server]
+channel_id = [1, 2]
+privilege_group_id = 5
+default_permission = { i_channel_needed_delete_power = 75 }
+
+[[permissions]]
+channel_id = 3
+map = [[86, 75], [999, 1]]
+
+[misc]
+
+[raw_query]
+user = "serveradmin"
+password = "114514"
+"#,
+        )
+        .unwrap();
+        let mock = MockServer::start().await;
+        let mut conn = SocketConn::connect(&mock.endpoint()).await.unwrap();
+        mock.set_reply("channellist", "cid=1 pid=0 channel_order=0 channel_name=Lobby total_clients=0 channel_needed_subscribe_power=0");
+        mock.set_reply(
+            "channelgrouplist",
+            "cgid=5 name=Channel\\sAdmin|cgid=8 name=Guest",
+        );
+        mock.set_reply(
+            "permissionlist",
+            "permid=86 permname=i_channel_needed_permission_modify_power",
+        );
+
+        let problems = validate(&mut conn, &config, config.server()).await.unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                "Monitored channel 2 not found",
+                "Unknown permission name: i_channel_needed_delete_power",
+                "Channel 3 in permissions not found",
+                "Unknown permission id 999 of channel 3",
+            ]
+        );
+
+        mock.set_reply(
+            "permissionlist",
+            "permid=86 permname=i_channel_needed_permission_modify_power|permid=133 permname=i_channel_needed_delete_power",
+        );
+        mock.set_reply("channelgrouplist", "cgid=8 name=Guest");
+        let problems = validate(&mut conn, &config, config.server()).await.unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                "Monitored channel 2 not found",
+                "Channel group 5 (privilege_group_id) not found",
+                "Channel 3 in permissions not found",
+                "Unknown permission id 999 of channel 3",
+            ]
+        );
+    }
+}
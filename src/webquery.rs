@@ -0,0 +1,387 @@
+use crate::datastructures::config::{ChannelProperties, ChannelType};
+use crate::datastructures::{
+    Channel, ChannelGroup, Client, ClientDatabaseId, CreateChannel, FromJSON, Notifies,
+    PermissionInfo, QueryError, QueryResult, ServerInfo, WebQueryStatus, WhoAmI,
+};
+use crate::metrics::METRICS;
+use crate::query::QueryConn;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use serde_json::{Map, Value};
+use std::time::{Duration, Instant};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+struct WebQueryResponse {
+    body: Option<Vec<Value>>,
+    status: WebQueryStatus,
+}
+
+pub struct WebQueryConn {
+    client: reqwest::Client,
+    server: String,
+    api_key: String,
+    server_id: i64,
+    last_active: Instant,
+}
+
+impl WebQueryConn {
+    pub async fn connect(server: &str, api_key: &str, server_id: i64) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| anyhow!("Build http client error: {:?}", e))?;
+        let mut self_ = Self {
+            client,
+            server: server.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            server_id,
+            last_active: Instant::now(),
+        };
+        // Check server and api key are available
+        self_
+            .basic_operation("version", &[])
+            .await
+            .map_err(|e| anyhow!("Got error while connect to {} {:?}", server, e))?;
+        Ok(self_)
+    }
+
+    async fn request(
+        &mut self,
+        command: &str,
+        params: &[(&str, String)],
+    ) -> QueryResult<Option<Vec<Value>>> {
+        let body = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.clone())))
+            .collect::<Map<_, _>>();
+        let response = self
+            .client
+            .post(format!("{}/{}/{}", self.server, self.server_id, command))
+            .header("x-api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Got error while request {}: {:?}", command, e))?
+            .json::<WebQueryResponse>()
+            .await
+            .map_err(|e| anyhow!("Got error while parse {} response: {:?}", command, e))?;
+        self.last_active = Instant::now();
+        response
+            .status
+            .into_status()
+            .into_result(response.body)
+            .inspect_err(|e| METRICS.query_error(e.code()))
+    }
+
+    async fn basic_operation(
+        &mut self,
+        command: &str,
+        params: &[(&str, String)],
+    ) -> QueryResult<()> {
+        self.request(command, params).await.map(|_| ())
+    }
+
+    async fn query_operation<T: FromJSON + Sized>(
+        &mut self,
+        command: &str,
+        params: &[(&str, String)],
+    ) -> QueryResult<Option<Vec<T>>> {
+        Ok(self
+            .request(command, params)
+            .await?
+            .map(|body| body.into_iter().map(T::from_value).collect())
+            .transpose()?)
+    }
+
+    async fn query_operation_non_error<T: FromJSON + Sized>(
+        &mut self,
+        command: &str,
+    ) -> QueryResult<Vec<T>> {
+        self.query_operation(command, &[])
+            .await?
+            .ok_or_else(QueryError::static_empty_response)
+    }
+}
+
+#[async_trait]
+impl QueryConn for WebQueryConn {
+    fn support_notifies(&self) -> bool {
+        false
+    }
+
+    async fn wait_notifies(&mut self, timeout: Duration) -> anyhow::Result<Vec<Notifies>> {
+        tokio::time::sleep(timeout).await;
+        Ok(vec![])
+    }
+
+    fn take_reconnected(&mut self) -> bool {
+        false
+    }
+
+    async fn register_notifies(&mut self) -> QueryResult<()> {
+        Err(anyhow!("WebQuery does not support notifies").into())
+    }
+
+    fn last_active(&self) -> Instant {
+        self.last_active
+    }
+
+    // Every request is stateless, nothing to keep alive
+    async fn keepalive(&mut self) -> QueryResult<()> {
+        self.last_active = Instant::now();
+        Ok(())
+    }
+
+    async fn who_am_i(&mut self) -> QueryResult<WhoAmI> {
+        self.query_operation_non_error("whoami")
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    async fn send_text_message(&mut self, clid: i64, text: &str) -> QueryResult<()> {
+        self.basic_operation(
+            "sendtextmessage",
+            &[
+                ("targetmode", "1".to_string()),
+                ("target", clid.to_string()),
+                ("msg", text.to_string()),
+            ],
+        )
+        .await
+    }
+
+    async fn query_server_info(&mut self) -> QueryResult<ServerInfo> {
+        self.query_operation_non_error("serverinfo")
+            .await
+            .map(|mut v| v.remove(0))
+    }
+
+    async fn query_channels(&mut self) -> QueryResult<Vec<Channel>> {
+        self.query_operation_non_error("channellist").await
+    }
+
+    async fn delete_channel(&mut self, cid: i64, force: bool) -> QueryResult<()> {
+        self.basic_operation(
+            "channeldelete",
+            &[
+                ("cid", cid.to_string()),
+                ("force", (force as u8).to_string()),
+            ],
+        )
+        .await
+    }
+
+    async fn create_channel(
+        &mut self,
+        name: &str,
+        pid: i64,
+        channel_type: Option<ChannelType>,
+        properties: &ChannelProperties,
+    ) -> QueryResult<Option<CreateChannel>> {
+        let mut params = vec![
+            ("channel_name", name.to_string()),
+            ("cpid", pid.to_string()),
+        ];
+        params.extend(properties.to_pairs());
+        if let Some(channel_type) = channel_type {
+            params.push((channel_type.flag(), "1".to_string()));
+        }
+        self.query_operation("channelcreate", &params)
+            .await
+            .map(|r| r.map(|mut v| v.swap_remove(0)))
+    }
+
+    async fn edit_channel(&mut self, cid: i64, properties: &[(&str, String)]) -> QueryResult<()> {
+        let mut params = vec![("cid", cid.to_string())];
+        params.extend(properties.iter().cloned());
+        self.basic_operation("channeledit", &params).await
+    }
+
+    async fn query_clients(&mut self) -> QueryResult<Vec<Client>> {
+        self.query_operation_non_error("clientlist?-uid").await
+    }
+
+    async fn query_client_database_id(&mut self, uid: &str) -> QueryResult<ClientDatabaseId> {
+        self.query_operation("clientgetdbidfromuid", &[("cluid", uid.to_string())])
+            .await?
+            .map(|mut v| v.remove(0))
+            .ok_or_else(QueryError::static_empty_response)
+    }
+
+    async fn kick_client_from_channel(&mut self, clid: i64, reason: &str) -> QueryResult<()> {
+        self.basic_operation(
+            "clientkick",
+            &[
+                ("clid", clid.to_string()),
+                ("reasonid", "4".to_string()),
+                ("reasonmsg", reason.to_string()),
+            ],
+        )
+        .await
+    }
+
+    async fn move_client_to_channel(&mut self, clid: i64, target_channel: i64) -> QueryResult<()> {
+        self.basic_operation(
+            "clientmove",
+            &[
+                ("clid", clid.to_string()),
+                ("cid", target_channel.to_string()),
+            ],
+        )
+        .await
+    }
+
+    async fn set_client_channel_group(
+        &mut self,
+        client_database_id: i64,
+        channel_id: i64,
+        group_id: i64,
+    ) -> QueryResult<()> {
+        self.basic_operation(
+            "setclientchannelgroup",
+            &[
+                ("cgid", group_id.to_string()),
+                ("cid", channel_id.to_string()),
+                ("cldbid", client_database_id.to_string()),
+            ],
+        )
+        .await
+    }
+
+    // Pipe separated parameter blocks are not available in JSON body, send one by one
+    async fn add_channel_permission(
+        &mut self,
+        target_channel: i64,
+        permissions: &[(u64, i64)],
+    ) -> QueryResult<()> {
+        for (permid, value) in permissions {
+            self.basic_operation(
+                "channeladdperm",
+                &[
+                    ("cid", target_channel.to_string()),
+                    ("permid", permid.to_string()),
+                    ("permvalue", value.to_string()),
+                ],
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn query_channel_groups(&mut self) -> QueryResult<Vec<ChannelGroup>> {
+        self.query_operation_non_error("channelgrouplist").await
+    }
+
+    async fn query_permission_list(&mut self) -> QueryResult<Vec<PermissionInfo>> {
+        self.query_operation_non_error("permissionlist").await
+    }
+
+    async fn logout(&mut self) -> QueryResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datastructures::config::ChannelProperties;
+    use crate::query::QueryConn;
+    use crate::webquery::WebQueryConn;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Minimal HTTP stand-in, answer each request by its command and close connection
+    async fn serve(listener: TcpListener) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            loop {
+                let size = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..size]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            let text = String::from_utf8_lossy(&request).to_string();
+            let path = text.split_whitespace().nth(1).unwrap().to_string();
+            let response = if !text.to_lowercase().contains("x-api-key: secret") {
+                r#"{"status":{"code":5122,"message":"invalid apikey"}}"#
+            } else {
+                match path.as_str() {
+                    "/1/version" => {
+                        r#"{"body":[{"version":"3.13.7"}],"status":{"code":0,"message":"ok"}}"#
+                    }
+                    "/1/clientlist?-uid" => {
+                        r#"{"body":[{"clid":"8","cid":"1","client_database_id":"3","client_nickname":"foo","client_type":"0","client_unique_identifier":"foo="}],"status":{"code":0,"message":"ok"}}"#
+                    }
+                    "/1/channelcreate" if text.contains(r#""channel_name":"taken""#) => {
+                        r#"{"status":{"code":771,"message":"channel name is already in use"}}"#
+                    }
+                    "/1/channelcreate" => {
+                        r#"{"body":[{"cid":"12"}],"status":{"code":0,"message":"ok"}}"#
+                    }
+                    _ => r#"{"status":{"code":256,"message":"command not found"}}"#,
+                }
+            };
+            stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        response.len(),
+                        response
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener));
+
+        assert!(WebQueryConn::connect(&server, "wrong", 1).await.is_err());
+        let mut conn = WebQueryConn::connect(&server, "secret", 1).await.unwrap();
+
+        let clients = conn.query_clients().await.unwrap();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(clients[0].client_database_id(), 3);
+        assert_eq!(clients[0].client_nickname(), "foo");
+        assert_eq!(clients[0].client_unique_identifier(), "foo=");
+
+        let properties = ChannelProperties::default();
+        let channel = conn
+            .create_channel("bar", 1, None, &properties)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(channel.cid(), 12);
+        let err = conn
+            .create_channel("taken", 1, None, &properties)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), 771);
+
+        assert_eq!(
+            conn.delete_channel(12, false).await.unwrap_err().code(),
+            256
+        );
+    }
+}